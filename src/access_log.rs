@@ -0,0 +1,602 @@
+use std::{
+    borrow::Cow,
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::PathBuf,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use http::{
+    header::{REFERER, USER_AGENT},
+    HeaderMap, HeaderValue, Method, Request, Response, StatusCode, Version,
+};
+use hyper::body::{Bytes, HttpBody, SizeHint};
+use prometheus::IntCounter;
+use serde::Serialize;
+
+/// Writes one line per request to the access log.
+///
+/// The access log is configured using environment variables:
+///
+/// - `ACCESS_LOG`: `-` to write to stdout (the default), `off` to disable
+///   logging, or a path to a file to append to.
+/// - `ACCESS_LOG_FORMAT`: `combined` (the default), `common`, or `json`.
+/// - `ACCESS_LOG_MAX_BYTES`: When logging to a file, the size at which the file
+///   is rotated. Defaults to 64 MiB. `0` disables rotation.
+/// - `ACCESS_LOG_KEEP`: The number of rotated files to keep. Defaults to 5.
+/// - `TRUSTED_PROXIES`: A comma-separated list of proxy IP addresses whose
+///   `X-Forwarded-For` header is honored. Defaults to `127.0.0.1,::1`.
+#[derive(Debug, Clone)]
+pub(crate) struct AccessLog {
+    data: Option<Arc<AccessLogData>>,
+}
+
+#[derive(Debug)]
+struct AccessLogData {
+    sender: flume::Sender<String>,
+    format: LogFormat,
+    trusted_proxies: Vec<IpAddr>,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum LogFormat {
+    Common,
+    Combined,
+    Json,
+}
+
+enum Destination {
+    Stdout,
+    File(RotatingFile),
+}
+
+impl AccessLog {
+    pub(crate) fn from_env() -> anyhow::Result<Self> {
+        let destination = std::env::var("ACCESS_LOG").unwrap_or_else(|_| String::from("-"));
+        let destination = match destination.as_str() {
+            "off" => return Ok(Self { data: None }),
+            "-" => Destination::Stdout,
+            path => {
+                let max_bytes = env_or("ACCESS_LOG_MAX_BYTES", 64 * 1024 * 1024)?;
+                let keep = env_or("ACCESS_LOG_KEEP", 5)?;
+                Destination::File(RotatingFile::open(PathBuf::from(path), max_bytes, keep)?)
+            }
+        };
+        let format = match std::env::var("ACCESS_LOG_FORMAT").as_deref() {
+            Err(_) | Ok("combined") => LogFormat::Combined,
+            Ok("common") => LogFormat::Common,
+            Ok("json") => LogFormat::Json,
+            Ok(other) => anyhow::bail!("unknown ACCESS_LOG_FORMAT: {other}"),
+        };
+        let trusted_proxies = match std::env::var("TRUSTED_PROXIES") {
+            Ok(proxies) => proxies
+                .split(',')
+                .map(str::trim)
+                .filter(|proxy| !proxy.is_empty())
+                .map(|proxy| {
+                    proxy.parse().map_err(|_| {
+                        anyhow::anyhow!("invalid proxy address in TRUSTED_PROXIES: {proxy}")
+                    })
+                })
+                .collect::<Result<_, _>>()?,
+            Err(_) => vec![
+                IpAddr::V4(Ipv4Addr::LOCALHOST),
+                IpAddr::V6(Ipv6Addr::LOCALHOST),
+            ],
+        };
+
+        let (sender, receiver) = flume::unbounded();
+        std::thread::Builder::new()
+            .name(String::from("access-log"))
+            .spawn(move || write_log_lines(destination, receiver))?;
+
+        Ok(Self {
+            data: Some(Arc::new(AccessLogData {
+                sender,
+                format,
+                trusted_proxies,
+            })),
+        })
+    }
+
+    /// Captures the information needed to log `request`. Must be called before
+    /// the request is handled.
+    pub(crate) fn request_info(
+        &self,
+        request: &Request<hyper::Body>,
+        peer_addr: SocketAddr,
+    ) -> Option<RequestInfo> {
+        let data = self.data.as_ref()?;
+        let headers = request.headers();
+        Some(RequestInfo {
+            client: client_address(peer_addr.ip(), headers, &data.trusted_proxies),
+            method: request.method().clone(),
+            target: request
+                .uri()
+                .path_and_query()
+                .map_or_else(|| request.uri().path().to_string(), ToString::to_string),
+            version: request.version(),
+            user_agent: header_string(headers.get(USER_AGENT)),
+            referer: header_string(headers.get(REFERER)),
+            received: SystemTime::now(),
+            start: Instant::now(),
+        })
+    }
+
+    /// Wraps the body of `response` so that the request is logged once the
+    /// body has been sent or the connection is closed. The number of body bytes
+    /// sent is also added to `bytes_served`.
+    pub(crate) fn log_response(
+        &self,
+        request: Option<RequestInfo>,
+        response: Response<hyper::Body>,
+        bytes_served: IntCounter,
+    ) -> Response<LoggedBody> {
+        let pending = match (&self.data, request) {
+            (Some(data), Some(request)) => Some(PendingEntry {
+                data: data.clone(),
+                request,
+                status: response.status(),
+            }),
+            _ => None,
+        };
+        response.map(|body| LoggedBody {
+            body,
+            bytes_sent: 0,
+            bytes_served: Some(bytes_served),
+            pending,
+        })
+    }
+}
+
+pub(crate) fn env_or<T: std::str::FromStr>(name: &str, default: T) -> anyhow::Result<T> {
+    match std::env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map_err(|_| anyhow::anyhow!("invalid value for {name}: {value}")),
+        Err(_) => Ok(default),
+    }
+}
+
+fn header_string(value: Option<&HeaderValue>) -> Option<String> {
+    value.map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+}
+
+/// Returns the address of the client that made the request.
+///
+/// When the peer is a trusted proxy, the `X-Forwarded-For` header is walked
+/// from right to left, skipping trusted proxies, and the first untrusted
+/// address is returned. Addresses to the left of it could have been forged by
+/// the client and are ignored.
+fn client_address(peer: IpAddr, headers: &HeaderMap, trusted_proxies: &[IpAddr]) -> IpAddr {
+    if !trusted_proxies.contains(&peer) {
+        return peer;
+    }
+
+    let mut client = peer;
+    for forwarded_for in headers.get_all("x-forwarded-for").iter().rev() {
+        let forwarded_for = match forwarded_for.to_str() {
+            Ok(forwarded_for) => forwarded_for,
+            Err(_) => return client,
+        };
+        for address in forwarded_for.rsplit(',') {
+            match address.trim().parse::<IpAddr>() {
+                Ok(address) if trusted_proxies.contains(&address) => client = address,
+                Ok(address) => return address,
+                Err(_) => return client,
+            }
+        }
+    }
+    client
+}
+
+#[derive(Debug)]
+pub(crate) struct RequestInfo {
+    client: IpAddr,
+    method: Method,
+    target: String,
+    version: Version,
+    user_agent: Option<String>,
+    referer: Option<String>,
+    received: SystemTime,
+    start: Instant,
+}
+
+#[derive(Debug)]
+struct PendingEntry {
+    data: Arc<AccessLogData>,
+    request: RequestInfo,
+    status: StatusCode,
+}
+
+impl PendingEntry {
+    fn finish(self, bytes_sent: u64) {
+        let line = format_entry(self.data.format, &self.request, self.status, bytes_sent);
+        drop(self.data.sender.send(line));
+    }
+}
+
+#[derive(Serialize)]
+struct JsonEntry<'a> {
+    time: String,
+    client: IpAddr,
+    method: &'a str,
+    path: &'a str,
+    protocol: String,
+    status: u16,
+    bytes_sent: u64,
+    duration_ms: f64,
+    user_agent: Option<&'a str>,
+    referer: Option<&'a str>,
+}
+
+fn format_entry(
+    format: LogFormat,
+    request: &RequestInfo,
+    status: StatusCode,
+    bytes_sent: u64,
+) -> String {
+    if format == LogFormat::Json {
+        return serde_json::to_string(&JsonEntry {
+            time: rfc3339_timestamp(request.received),
+            client: request.client,
+            method: request.method.as_str(),
+            path: &request.target,
+            protocol: format!("{:?}", request.version),
+            status: status.as_u16(),
+            bytes_sent,
+            duration_ms: request.start.elapsed().as_secs_f64() * 1_000.,
+            user_agent: request.user_agent.as_deref(),
+            referer: request.referer.as_deref(),
+        })
+        .expect("serialization can't fail");
+    }
+
+    let mut line = format!(
+        "{} - - [{}] \"{} {} {:?}\" {} {}",
+        request.client,
+        clf_timestamp(request.received),
+        request.method,
+        escape(&request.target),
+        request.version,
+        status.as_u16(),
+        bytes_sent,
+    );
+    if format == LogFormat::Combined {
+        line.push_str(&format!(
+            " \"{}\" \"{}\"",
+            escape(request.referer.as_deref().unwrap_or("-")),
+            escape(request.user_agent.as_deref().unwrap_or("-"))
+        ));
+    }
+    line
+}
+
+/// Escapes quotes, backslashes and control characters so that a value can be
+/// placed inside of a quoted log field.
+fn escape(value: &str) -> Cow<'_, str> {
+    if !value
+        .chars()
+        .any(|ch| ch == '"' || ch == '\\' || ch.is_control())
+    {
+        return Cow::Borrowed(value);
+    }
+
+    let mut escaped = String::with_capacity(value.len() + 2);
+    for ch in value.chars() {
+        match ch {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            ch if ch.is_control() => escaped.push_str(&format!("\\x{:02x}", u32::from(ch))),
+            ch => escaped.push(ch),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+struct DateTime {
+    year: i64,
+    month: u32,
+    day: u32,
+    hour: u64,
+    minute: u64,
+    second: u64,
+    millisecond: u32,
+}
+
+impl DateTime {
+    fn from_system_time(time: SystemTime) -> Self {
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let seconds = since_epoch.as_secs();
+        let days = (seconds / 86_400) as i64;
+        let seconds_of_day = seconds % 86_400;
+
+        // Converts days since the epoch to a proleptic Gregorian date, using
+        // Howard Hinnant's `civil_from_days` algorithm.
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let day_of_era = z.rem_euclid(146_097);
+        let year_of_era =
+            (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+        let month = if shifted_month < 10 {
+            shifted_month + 3
+        } else {
+            shifted_month - 9
+        } as u32;
+        let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+        Self {
+            year,
+            month,
+            day,
+            hour: seconds_of_day / 3_600,
+            minute: seconds_of_day / 60 % 60,
+            second: seconds_of_day % 60,
+            millisecond: since_epoch.subsec_millis(),
+        }
+    }
+}
+
+/// Formats `time` as `18/Oct/2026:12:34:56 +0000`.
+fn clf_timestamp(time: SystemTime) -> String {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let time = DateTime::from_system_time(time);
+    format!(
+        "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+        time.day,
+        MONTHS[time.month as usize - 1],
+        time.year,
+        time.hour,
+        time.minute,
+        time.second
+    )
+}
+
+/// Formats `time` as `2026-10-18T12:34:56.789Z`.
+fn rfc3339_timestamp(time: SystemTime) -> String {
+    let time = DateTime::from_system_time(time);
+    format!(
+        "{}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        time.year, time.month, time.day, time.hour, time.minute, time.second, time.millisecond
+    )
+}
+
+/// A response body that logs its request once the body has been fully sent,
+/// or when it is dropped because the connection was closed.
+pub(crate) struct LoggedBody {
+    body: hyper::Body,
+    bytes_sent: u64,
+    bytes_served: Option<IntCounter>,
+    pending: Option<PendingEntry>,
+}
+
+impl LoggedBody {
+    fn finish(&mut self) {
+        if let Some(bytes_served) = self.bytes_served.take() {
+            bytes_served.inc_by(self.bytes_sent);
+        }
+        if let Some(pending) = self.pending.take() {
+            pending.finish(self.bytes_sent);
+        }
+    }
+}
+
+impl HttpBody for LoggedBody {
+    type Data = Bytes;
+    type Error = hyper::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let result = Pin::new(&mut self.body).poll_data(cx);
+        match &result {
+            Poll::Ready(Some(Ok(data))) => self.bytes_sent += data.len() as u64,
+            Poll::Ready(None) => self.finish(),
+            _ => {}
+        }
+        result
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        Pin::new(&mut self.body).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.body.size_hint()
+    }
+}
+
+impl Drop for LoggedBody {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+fn write_log_lines(mut destination: Destination, receiver: flume::Receiver<String>) {
+    let stdout = io::stdout();
+    while let Ok(line) = receiver.recv() {
+        let result = match &mut destination {
+            Destination::Stdout => writeln!(stdout.lock(), "{line}"),
+            Destination::File(file) => file.write_line(&line, receiver.is_empty()),
+        };
+        if let Err(err) = result {
+            eprintln!("Error writing access log: {err}");
+        }
+    }
+}
+
+/// A log file that is renamed to `<path>.1` once it grows beyond `max_bytes`.
+/// Previously rotated files are shifted up, keeping at most `keep` of them.
+struct RotatingFile {
+    path: PathBuf,
+    file: BufWriter<File>,
+    len: u64,
+    max_bytes: u64,
+    keep: usize,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_bytes: u64, keep: usize) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let len = file.metadata()?.len();
+        Ok(Self {
+            path,
+            file: BufWriter::new(file),
+            len,
+            max_bytes,
+            keep,
+        })
+    }
+
+    fn write_line(&mut self, line: &str, flush: bool) -> io::Result<()> {
+        let line_len = line.len() as u64 + 1;
+        if self.max_bytes > 0 && self.len > 0 && self.len + line_len > self.max_bytes {
+            self.rotate()?;
+        }
+
+        writeln!(self.file, "{line}")?;
+        self.len += line_len;
+        if flush {
+            self.file.flush()?;
+        }
+        Ok(())
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut path = OsString::from(self.path.as_os_str());
+        path.push(format!(".{index}"));
+        PathBuf::from(path)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for index in (1..self.keep).rev() {
+                let from = self.rotated_path(index);
+                if from.exists() {
+                    fs::rename(from, self.rotated_path(index + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated_path(1))?;
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.file = BufWriter::new(file);
+        self.len = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn timestamps() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_792_326_896_789);
+        assert_eq!(clf_timestamp(time), "18/Oct/2026:12:34:56 +0000");
+        assert_eq!(rfc3339_timestamp(time), "2026-10-18T12:34:56.789Z");
+        assert_eq!(clf_timestamp(UNIX_EPOCH), "01/Jan/1970:00:00:00 +0000");
+        let leap_day = UNIX_EPOCH + Duration::from_secs(951_782_400);
+        assert_eq!(rfc3339_timestamp(leap_day), "2000-02-29T00:00:00.000Z");
+    }
+
+    #[test]
+    fn forwarded_for() {
+        let proxy = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let trusted = [proxy, "10.0.0.1".parse().unwrap()];
+        let headers = |values: &[&str]| {
+            let mut headers = HeaderMap::new();
+            for value in values {
+                headers.append("x-forwarded-for", HeaderValue::from_str(value).unwrap());
+            }
+            headers
+        };
+        let ip = |ip: &str| ip.parse::<IpAddr>().unwrap();
+
+        assert_eq!(client_address(proxy, &headers(&[]), &trusted), proxy);
+        assert_eq!(
+            client_address(proxy, &headers(&["203.0.113.1"]), &trusted),
+            ip("203.0.113.1")
+        );
+        // Untrusted peers can't spoof their address.
+        assert_eq!(
+            client_address(ip("192.0.2.1"), &headers(&["203.0.113.1"]), &trusted),
+            ip("192.0.2.1")
+        );
+        // Only the rightmost untrusted address is used.
+        assert_eq!(
+            client_address(
+                proxy,
+                &headers(&["198.51.100.1, 203.0.113.1, 10.0.0.1"]),
+                &trusted
+            ),
+            ip("203.0.113.1")
+        );
+        assert_eq!(
+            client_address(proxy, &headers(&["198.51.100.1", "2001:db8::1"]), &trusted),
+            ip("2001:db8::1")
+        );
+        assert_eq!(
+            client_address(proxy, &headers(&["garbage, 10.0.0.1"]), &trusted),
+            ip("10.0.0.1")
+        );
+    }
+
+    #[test]
+    fn formats() {
+        let request = RequestInfo {
+            client: "203.0.113.1".parse().unwrap(),
+            method: Method::GET,
+            target: String::from("/dossier/?a=b"),
+            version: Version::HTTP_11,
+            user_agent: Some(String::from("agent \"quoted\"")),
+            referer: None,
+            received: UNIX_EPOCH,
+            start: Instant::now(),
+        };
+        assert_eq!(
+            format_entry(LogFormat::Common, &request, StatusCode::OK, 42),
+            "203.0.113.1 - - [01/Jan/1970:00:00:00 +0000] \"GET /dossier/?a=b HTTP/1.1\" 200 42"
+        );
+        assert_eq!(
+            format_entry(LogFormat::Combined, &request, StatusCode::NOT_FOUND, 9),
+            "203.0.113.1 - - [01/Jan/1970:00:00:00 +0000] \"GET /dossier/?a=b HTTP/1.1\" 404 9 \"-\" \"agent \\\"quoted\\\"\""
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&format_entry(LogFormat::Json, &request, StatusCode::OK, 42))
+                .unwrap();
+        assert_eq!(json["client"], "203.0.113.1");
+        assert_eq!(json["path"], "/dossier/?a=b");
+        assert_eq!(json["protocol"], "HTTP/1.1");
+        assert_eq!(json["status"], 200);
+        assert_eq!(json["bytes_sent"], 42);
+        assert_eq!(json["user_agent"], "agent \"quoted\"");
+        assert_eq!(json["referer"], serde_json::Value::Null);
+    }
+}
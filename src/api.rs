@@ -1,4 +1,14 @@
-use std::{collections::HashMap, future::Future};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    future::Future,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, OnceLock,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
 use bonsaidb::{
     core::{
@@ -6,20 +16,30 @@ use bonsaidb::{
         arc_bytes::serde::Bytes,
         async_trait::async_trait,
         connection::{AsyncConnection, AsyncStorageConnection, HasSession},
-        schema::NamedCollection,
+        document::CollectionDocument,
+        schema::{NamedCollection, SerializedCollection},
+    },
+    files::{
+        direct::{Async, File},
+        FileConfig, Truncate,
     },
-    files::{FileConfig, Truncate},
     server::{
         api::{Handler, HandlerError, HandlerResult, HandlerSession},
         ServerDatabase,
     },
 };
 use futures::StreamExt;
+use parking_lot::RwLock;
 use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, SeekFrom};
 
 use crate::{
-    permissions::{project_resource_name, DossierAction},
-    schema::{Dossier, DossierFiles, Metadata, Project},
+    compactor,
+    metrics::metrics,
+    permissions::{project_resource_name, status_resource_name, DossierAction},
+    schema::{Alias, Dossier, DossierFiles, Metadata, Project, UploadSession},
+    status::{self, ServerStatus},
+    usage::{self, FileUsage, FolderUsage},
     CliBackend,
 };
 
@@ -44,6 +64,56 @@ pub enum ApiError {
     /// The file was deleted during the operation.
     #[error("the file was deleted during the operation")]
     Deleted,
+    /// A request would have deleted every file in a folder that contains
+    /// files, and `force` was not specified.
+    #[error("refusing to delete every file in the folder without force")]
+    WouldEmptyFolder,
+    /// The upload session was not found. It may have finished or expired.
+    #[error("upload session not found")]
+    UploadNotFound,
+    /// A chunk was written at an offset other than the end of the data the
+    /// server has acknowledged.
+    #[error("chunk offset does not match the {acknowledged} bytes already uploaded")]
+    UploadOffsetMismatch { acknowledged: u64 },
+    /// No file or folder exists at the path provided.
+    #[error("no file or folder exists at the path provided")]
+    NotFound,
+    /// A copy or move was requested where the destination is the source or
+    /// is contained within it.
+    #[error("the destination must not overlap the source")]
+    OverlappingPaths,
+    /// A write would have exceeded one of the project's quotas.
+    #[error("the project's {0} quota would be exceeded")]
+    QuotaExceeded(Quota),
+    /// A chunk would have made an upload longer than the length declared when
+    /// it started.
+    #[error("the upload would exceed the {declared} bytes declared when it started")]
+    UploadTooLong { declared: u64 },
+}
+
+/// A limit in a [`ProjectQuota`](crate::schema::ProjectQuota).
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Quota {
+    Bytes,
+    Files,
+    FileSize,
+}
+
+impl Display for Quota {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Quota::Bytes => "storage",
+            Quota::Files => "file count",
+            Quota::FileSize => "file size",
+        })
+    }
+}
+
+/// Converts an error reading from the filesystem or a file's contents.
+fn io_error(err: std::io::Error) -> HandlerError<ApiError> {
+    HandlerError::Server(bonsaidb::server::Error::from(bonsaidb::core::Error::other(
+        "io", err,
+    )))
 }
 
 trait ResultExt<T> {
@@ -83,13 +153,14 @@ impl Handler<CliBackend, ListFiles> for DossierApiHandler {
         session: HandlerSession<'_, CliBackend>,
         request: ListFiles,
     ) -> HandlerResult<ListFiles> {
-        handle_sync_op_with_permissions(
+        let result = handle_sync_op_with_permissions(
             session,
             &request.path,
             &request,
             |database, request| async move { list_files(&request.path, &database).await },
         )
-        .await
+        .await;
+        metrics().record_api_request("list-files", result)
     }
 }
 
@@ -107,6 +178,164 @@ pub async fn list_files<C: AsyncConnection + Clone>(
         .collect())
 }
 
+/// A file stored on the server.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RemoteFile {
+    pub path: String,
+    pub id: u32,
+    pub len: u64,
+    /// The blake3 hash of the file's contents, or `None` if the file has no
+    /// metadata because it was never finished.
+    pub blake3: Option<[u8; 32]>,
+}
+
+impl RemoteFile {
+    async fn new<C: AsyncConnection + Clone + Unpin + 'static>(
+        file: &mut File<Async<C>, DossierFiles>,
+    ) -> Result<Self, HandlerError<ApiError>> {
+        Ok(Self {
+            path: file.path(),
+            id: file.id(),
+            len: file.len().await?,
+            blake3: file.metadata().map(|metadata| metadata.blake3),
+        })
+    }
+}
+
+/// Lists every file within the folder `path`, sorted by path.
+#[derive(Serialize, Deserialize, Debug, Api)]
+#[api(name = "list-remote-files", response = Vec<RemoteFile>, error = ApiError)]
+pub struct ListRemoteFiles {
+    pub path: String,
+}
+
+#[async_trait]
+impl Handler<CliBackend, ListRemoteFiles> for DossierApiHandler {
+    async fn handle(
+        session: HandlerSession<'_, CliBackend>,
+        request: ListRemoteFiles,
+    ) -> HandlerResult<ListRemoteFiles> {
+        let result = handle_sync_op_with_permissions(
+            session,
+            &request.path,
+            &request,
+            |database, request| async move { list_remote_files(&request.path, &database).await },
+        )
+        .await;
+        metrics().record_api_request("list-remote-files", result)
+    }
+}
+
+pub async fn list_remote_files<C: AsyncConnection + Clone + Unpin + 'static>(
+    path: &str,
+    database: &C,
+) -> HandlerResult<ListRemoteFiles> {
+    if !path.starts_with('/') || !path.ends_with('/') {
+        return Err(HandlerError::Api(ApiError::InvalidPath));
+    }
+
+    let mut files = Vec::new();
+    for mut file in DossierFiles::list_recursive_async(path, database).await? {
+        files.push(RemoteFile::new(&mut file).await?);
+    }
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(files)
+}
+
+/// Returns the file at `path`, if it exists.
+#[derive(Serialize, Deserialize, Debug, Api)]
+#[api(name = "stat-file", response = Option<RemoteFile>, error = ApiError)]
+pub struct StatFile {
+    pub path: String,
+}
+
+#[async_trait]
+impl Handler<CliBackend, StatFile> for DossierApiHandler {
+    async fn handle(
+        session: HandlerSession<'_, CliBackend>,
+        request: StatFile,
+    ) -> HandlerResult<StatFile> {
+        let result = handle_sync_op_with_permissions(
+            session,
+            &request.path,
+            &request,
+            |database, request| async move { stat_file(&request.path, &database).await },
+        )
+        .await;
+        metrics().record_api_request("stat-file", result)
+    }
+}
+
+pub async fn stat_file<C: AsyncConnection + Clone + Unpin + 'static>(
+    path: &str,
+    database: &C,
+) -> HandlerResult<StatFile> {
+    match DossierFiles::load_async(path, database)
+        .await
+        .map_files_error()?
+    {
+        Some(mut file) => Ok(Some(RemoteFile::new(&mut file).await?)),
+        None => Ok(None),
+    }
+}
+
+/// The most data returned by a single [`ReadFile`] request.
+pub const MAX_READ_LEN: u64 = 1024 * 1024;
+
+/// Reads up to `len` bytes of the file at `path`, starting at `offset`. Fewer
+/// bytes are returned when the end of the file is reached, or when `len` is
+/// larger than [`MAX_READ_LEN`].
+#[derive(Serialize, Deserialize, Debug, Api)]
+#[api(name = "read-file", response = Bytes, error = ApiError)]
+pub struct ReadFile {
+    pub path: String,
+    pub offset: u64,
+    pub len: u64,
+}
+
+#[async_trait]
+impl Handler<CliBackend, ReadFile> for DossierApiHandler {
+    async fn handle(
+        session: HandlerSession<'_, CliBackend>,
+        request: ReadFile,
+    ) -> HandlerResult<ReadFile> {
+        let result = handle_sync_op_with_permissions(
+            session,
+            &request.path,
+            &request,
+            |database, request| async move {
+                read_file(&request.path, request.offset, request.len, &database).await
+            },
+        )
+        .await;
+        metrics().record_api_request("read-file", result)
+    }
+}
+
+pub async fn read_file<C: AsyncConnection + Clone + Unpin + 'static>(
+    path: &str,
+    offset: u64,
+    len: u64,
+    database: &C,
+) -> HandlerResult<ReadFile> {
+    let file = DossierFiles::load_async(path, database)
+        .await
+        .map_files_error()?
+        .ok_or(HandlerError::Api(ApiError::NotFound))?;
+    let mut contents = file.contents().await?;
+    contents
+        .seek(SeekFrom::Start(offset))
+        .await
+        .map_err(io_error)?;
+    let mut data = Vec::new();
+    contents
+        .take(len.min(MAX_READ_LEN))
+        .read_to_end(&mut data)
+        .await
+        .map_err(io_error)?;
+    Ok(Bytes::from(data))
+}
+
 #[derive(Serialize, Deserialize, Debug, Api)]
 #[api(name = "delete-file", response = bool, error = ApiError)]
 pub struct DeleteFile {
@@ -119,13 +348,14 @@ impl Handler<CliBackend, DeleteFile> for DossierApiHandler {
         session: HandlerSession<'_, CliBackend>,
         request: DeleteFile,
     ) -> HandlerResult<DeleteFile> {
-        handle_sync_op_with_permissions(
+        let result = handle_sync_op_with_permissions(
             session,
             &request.path,
             &request,
             |database, request| async move { delete_file(&request.path, &database).await },
         )
-        .await
+        .await;
+        metrics().record_api_request("delete-file", result)
     }
 }
 
@@ -139,80 +369,1063 @@ pub async fn delete_file<C: AsyncConnection + Clone>(
 }
 
 #[derive(Serialize, Deserialize, Debug, Api)]
-#[api(name = "write-file", response = Option<Bytes>, error = ApiError)]
-pub struct WriteFileData {
-    pub path: String,
-    pub data: Bytes,
-    pub start: bool,
-    pub finished: bool,
+#[api(name = "delete-files", response = usize, error = ApiError)]
+pub struct DeleteFiles {
+    /// The folder being synchronized. Every path must be inside of this
+    /// folder.
+    pub root: String,
+    pub paths: Vec<String>,
+    /// Allows deleting every file in `root`.
+    pub force: bool,
 }
 
 #[async_trait]
-impl Handler<CliBackend, WriteFileData> for DossierApiHandler {
+impl Handler<CliBackend, DeleteFiles> for DossierApiHandler {
     async fn handle(
         session: HandlerSession<'_, CliBackend>,
-        request: WriteFileData,
-    ) -> HandlerResult<WriteFileData> {
-        handle_sync_op_with_permissions(
+        request: DeleteFiles,
+    ) -> HandlerResult<DeleteFiles> {
+        let result = handle_sync_op_with_permissions(
             session,
-            &request.path,
+            &request.root,
             &request,
             |database, request| async move {
-                write_file_data(
-                    &request.path,
-                    &request.data,
-                    request.start,
-                    request.finished,
-                    &database,
-                )
-                .await
+                delete_files(&request.root, &request.paths, request.force, &database).await
             },
         )
-        .await
+        .await;
+        metrics().record_api_request("delete-files", result)
     }
 }
 
-pub async fn write_file_data<C: AsyncConnection + Clone + Unpin + 'static>(
-    path: &str,
-    data: &[u8],
-    start: bool,
-    finished: bool,
+/// Deletes `paths`, returning the number of files deleted.
+///
+/// Unless `force` is true, this refuses to delete every file in `root`, which
+/// protects against a sync from an empty or incorrect directory.
+pub async fn delete_files<C: AsyncConnection + Clone>(
+    root: &str,
+    paths: &[String],
+    force: bool,
     database: &C,
-) -> HandlerResult<WriteFileData> {
-    let mut file = match DossierFiles::load_async(path, database)
-        .await
-        .map_files_error()?
-    {
-        Some(file) if start => {
-            file.truncate(0, Truncate::RemovingStart).await?;
-            file
+) -> HandlerResult<DeleteFiles> {
+    if !root.ends_with('/') || paths.iter().any(|path| !path.starts_with(root)) {
+        return Err(HandlerError::Api(ApiError::InvalidPath));
+    }
+
+    if !force {
+        let existing = DossierFiles::list_recursive_async(root, database).await?;
+        let paths = paths.iter().map(String::as_str).collect::<HashSet<_>>();
+        if !existing.is_empty()
+            && existing
+                .iter()
+                .all(|file| paths.contains(file.path().as_str()))
+        {
+            return Err(HandlerError::Api(ApiError::WouldEmptyFolder));
         }
-        Some(file) => file,
-        None if start => DossierFiles::build(path)
-            .create_async(database)
+    }
+
+    let mut deleted = 0;
+    for path in paths {
+        if DossierFiles::delete_async(path, database)
             .await
-            .map_files_error()?,
-        None => return Err(HandlerError::Api(ApiError::Deleted)),
-    };
+            .map_files_error()?
+        {
+            deleted += 1;
+        }
+    }
+    Ok(deleted)
+}
+
+/// The state of an upload session.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct UploadStatus {
+    pub session_id: u64,
+    /// The number of bytes the server has written. The next chunk must start
+    /// at this offset.
+    pub offset: u64,
+}
+
+/// Begins uploading a file of `len` bytes to `path`, returning a session that
+/// chunks are written to.
+///
+/// The project's quota is checked once, when the upload starts. Until the
+/// upload finishes or expires, `len` bytes are reserved for it, and chunks
+/// can't write more than that.
+#[derive(Serialize, Deserialize, Debug, Api)]
+#[api(name = "start-upload", response = UploadStatus, error = ApiError)]
+pub struct StartUpload {
+    pub path: String,
+    pub len: u64,
+}
+
+#[async_trait]
+impl Handler<CliBackend, StartUpload> for DossierApiHandler {
+    async fn handle(
+        session: HandlerSession<'_, CliBackend>,
+        request: StartUpload,
+    ) -> HandlerResult<StartUpload> {
+        let result =
+            handle_sync_op_with_permissions(
+                session,
+                &request.path,
+                &request,
+                |database, request| async move {
+                    start_upload(&request.path, request.len, &database).await
+                },
+            )
+            .await;
+        metrics().record_api_request("start-upload", result)
+    }
+}
+
+pub async fn start_upload<C: AsyncConnection + Clone + Unpin + 'static>(
+    path: &str,
+    len: u64,
+    database: &C,
+) -> HandlerResult<StartUpload> {
+    let _guard = quota_lock().lock().await;
+    check_quota(&[(path.to_string(), len)], None, database).await?;
+
+    let file = DossierFiles::build(&staged_upload_path())
+        .create_async(database)
+        .await
+        .map_files_error()?;
+
+    let upload = UploadSession {
+        path: path.to_string(),
+        file_id: file.id(),
+        len,
+        last_activity: unix_timestamp(),
+    }
+    .insert_into_async(&upload_session_id(&file), database)
+    .await
+    .map_err(|err| err.error)?;
+
+    Ok(UploadStatus {
+        session_id: upload.header.id,
+        offset: 0,
+    })
+}
+
+/// Appends `data` to an upload session. `offset` must match the number of
+/// bytes already written, which prevents a retried chunk from being written
+/// twice.
+#[derive(Serialize, Deserialize, Debug, Api)]
+#[api(name = "upload-chunk", response = UploadStatus, error = ApiError)]
+pub struct UploadChunk {
+    pub session_id: u64,
+    pub offset: u64,
+    pub data: Bytes,
+}
+
+#[async_trait]
+impl Handler<CliBackend, UploadChunk> for DossierApiHandler {
+    async fn handle(
+        session: HandlerSession<'_, CliBackend>,
+        request: UploadChunk,
+    ) -> HandlerResult<UploadChunk> {
+        let result = handle_upload_op_with_permissions(
+            session,
+            request.session_id,
+            &request,
+            |database, upload, request| async move {
+                upload_chunk(upload, request.offset, &request.data, &database).await
+            },
+        )
+        .await;
+        metrics().record_api_request("upload-chunk", result)
+    }
+}
+
+pub async fn upload_chunk<C: AsyncConnection + Clone + Unpin + 'static>(
+    mut upload: CollectionDocument<UploadSession>,
+    offset: u64,
+    data: &[u8],
+    database: &C,
+) -> HandlerResult<UploadChunk> {
+    let mut file = DossierFiles::get_async(upload.contents.file_id, database)
+        .await?
+        .ok_or(HandlerError::Api(ApiError::Deleted))?;
+
+    // The file's length is the source of truth for how much data has been
+    // acknowledged, since it can't be updated atomically with the session.
+    let acknowledged = file.len().await?;
+    if acknowledged != offset {
+        return Err(HandlerError::Api(ApiError::UploadOffsetMismatch {
+            acknowledged,
+        }));
+    }
 
+    // The quota was checked for the declared length when the upload started.
+    if acknowledged + data.len() as u64 > upload.contents.len {
+        return Err(HandlerError::Api(ApiError::UploadTooLong {
+            declared: upload.contents.len,
+        }));
+    }
     file.append(data).await?;
 
-    if finished {
-        // Compute the hash of the file
+    upload.contents.last_activity = unix_timestamp();
+    upload.update_async(database).await?;
+
+    Ok(UploadStatus {
+        session_id: upload.header.id,
+        offset: offset + data.len() as u64,
+    })
+}
+
+/// Returns the current state of an upload session, allowing an interrupted
+/// upload to resume from the last acknowledged offset.
+#[derive(Serialize, Deserialize, Debug, Api)]
+#[api(name = "upload-status", response = UploadStatus, error = ApiError)]
+pub struct GetUploadStatus {
+    pub session_id: u64,
+}
+
+#[async_trait]
+impl Handler<CliBackend, GetUploadStatus> for DossierApiHandler {
+    async fn handle(
+        session: HandlerSession<'_, CliBackend>,
+        request: GetUploadStatus,
+    ) -> HandlerResult<GetUploadStatus> {
+        let result = handle_upload_op_with_permissions(
+            session,
+            request.session_id,
+            &request,
+            |database, upload, _request| async move { upload_status(upload, &database).await },
+        )
+        .await;
+        metrics().record_api_request("upload-status", result)
+    }
+}
+
+pub async fn upload_status<C: AsyncConnection + Clone + Unpin + 'static>(
+    upload: CollectionDocument<UploadSession>,
+    database: &C,
+) -> HandlerResult<GetUploadStatus> {
+    let mut file = DossierFiles::get_async(upload.contents.file_id, database)
+        .await?
+        .ok_or(HandlerError::Api(ApiError::Deleted))?;
+
+    Ok(UploadStatus {
+        session_id: upload.header.id,
+        offset: file.len().await?,
+    })
+}
+
+/// Completes an upload session, returning the blake3 hash of the file.
+#[derive(Serialize, Deserialize, Debug, Api)]
+#[api(name = "finish-upload", response = Bytes, error = ApiError)]
+pub struct FinishUpload {
+    pub session_id: u64,
+}
+
+#[async_trait]
+impl Handler<CliBackend, FinishUpload> for DossierApiHandler {
+    async fn handle(
+        session: HandlerSession<'_, CliBackend>,
+        request: FinishUpload,
+    ) -> HandlerResult<FinishUpload> {
+        let result = handle_upload_op_with_permissions(
+            session,
+            request.session_id,
+            &request,
+            |database, upload, _request| async move { finish_upload(upload, &database).await },
+        )
+        .await;
+        metrics().record_api_request("finish-upload", result)
+    }
+}
+
+pub async fn finish_upload<C: AsyncConnection + Clone + Unpin + 'static>(
+    upload: CollectionDocument<UploadSession>,
+    database: &C,
+) -> HandlerResult<FinishUpload> {
+    let mut file = DossierFiles::get_async(upload.contents.file_id, database)
+        .await?
+        .ok_or(HandlerError::Api(ApiError::Deleted))?;
+
+    let hash = compute_hash(&mut file).await?;
+    *file.metadata_mut() = Some(Metadata {
+        blake3: hash,
+        written_at: unix_timestamp(),
+    });
+    file.update_metadata().await?;
+    replace_file(file, &upload.contents.path, database).await?;
+
+    upload.delete_async(database).await?;
+
+    Ok(Bytes::from(hash.to_vec()))
+}
+
+/// Returns the id of the upload session that writes to the staged `file`. Each
+/// staged file belongs to at most one session, so sessions can be found from
+/// their file without scanning every session.
+fn upload_session_id<C: AsyncConnection + Clone + Unpin + 'static>(
+    file: &File<Async<C>, DossierFiles>,
+) -> u64 {
+    u64::from(file.id())
+}
+
+pub async fn load_upload_session<C: AsyncConnection>(
+    session_id: u64,
+    database: &C,
+) -> Result<CollectionDocument<UploadSession>, HandlerError<ApiError>> {
+    UploadSession::get_async(&session_id, database)
+        .await?
+        .ok_or(HandlerError::Api(ApiError::UploadNotFound))
+}
+
+/// Copies a file or folder. When `source` ends with a `/`, every file within it
+/// is copied into the folder `destination`, replacing existing files.
+#[derive(Serialize, Deserialize, Debug, Api)]
+#[api(name = "copy-files", response = usize, error = ApiError)]
+pub struct CopyFiles {
+    pub source: String,
+    pub destination: String,
+}
+
+#[async_trait]
+impl Handler<CliBackend, CopyFiles> for DossierApiHandler {
+    async fn handle(
+        session: HandlerSession<'_, CliBackend>,
+        request: CopyFiles,
+    ) -> HandlerResult<CopyFiles> {
+        let result = handle_transfer_op_with_permissions(
+            session,
+            &request.source,
+            &request.destination,
+            &request,
+            |database, request| async move {
+                copy_files(&request.source, &request.destination, &database).await
+            },
+        )
+        .await;
+        metrics().record_api_request("copy-files", result)
+    }
+}
+
+/// Copies `source` to `destination`, returning the number of files copied.
+pub async fn copy_files<C: AsyncConnection + Clone + Unpin + 'static>(
+    source: &str,
+    destination: &str,
+    database: &C,
+) -> HandlerResult<CopyFiles> {
+    let mut transfers = plan_transfers(source, destination, database).await?;
+    // The quota lock is held until the copies are in place, so that they can't
+    // race other writes to fit within a quota that they exceed together.
+    let _guard = quota_lock().lock().await;
+    check_transfer_quota(&mut transfers, database).await?;
+    let copied = transfers.len();
+    for (file, destination) in transfers {
+        // Copies are staged like uploads so that the destination is replaced
+        // only once the copy is complete.
+        let mut copy = DossierFiles::build(&staged_upload_path())
+            .create_async(database)
+            .await
+            .map_files_error()?;
         let mut contents = file.contents().await?;
-        let mut sha = blake3::Hasher::new();
         while let Some(block) = contents.next().await {
-            let block = block?;
-            sha.update(&block);
+            copy.append(&block?).await?;
         }
+        *copy.metadata_mut() = file.metadata().map(|metadata| Metadata {
+            written_at: unix_timestamp(),
+            ..metadata
+        });
+        copy.update_metadata().await?;
+        replace_file(copy, &destination, database).await?;
+    }
+    Ok(copied)
+}
 
-        let hash = sha.finalize().try_into().unwrap();
-        *file.metadata_mut() = Some(Metadata { blake3: hash });
-        file.update_metadata().await?;
+/// Moves a file or folder. When `source` ends with a `/`, every file within it
+/// is moved into the folder `destination`, replacing existing files.
+#[derive(Serialize, Deserialize, Debug, Api)]
+#[api(name = "move-files", response = usize, error = ApiError)]
+pub struct MoveFiles {
+    pub source: String,
+    pub destination: String,
+}
 
-        Ok(Some(Bytes::from(hash.to_vec())))
+#[async_trait]
+impl Handler<CliBackend, MoveFiles> for DossierApiHandler {
+    async fn handle(
+        session: HandlerSession<'_, CliBackend>,
+        request: MoveFiles,
+    ) -> HandlerResult<MoveFiles> {
+        let result = handle_transfer_op_with_permissions(
+            session,
+            &request.source,
+            &request.destination,
+            &request,
+            |database, request| async move {
+                move_files(&request.source, &request.destination, &database).await
+            },
+        )
+        .await;
+        metrics().record_api_request("move-files", result)
+    }
+}
+
+/// Moves `source` to `destination`, returning the number of files moved.
+pub async fn move_files<C: AsyncConnection + Clone + Unpin + 'static>(
+    source: &str,
+    destination: &str,
+    database: &C,
+) -> HandlerResult<MoveFiles> {
+    let mut transfers = plan_transfers(source, destination, database).await?;
+    // Moving files within a project doesn't change its usage. Otherwise, the
+    // quota lock is held until the files are moved, like for copies.
+    let _guard = if source.split('/').nth(1) != destination.split('/').nth(1) {
+        let guard = quota_lock().lock().await;
+        check_transfer_quota(&mut transfers, database).await?;
+        Some(guard)
     } else {
-        Ok(None)
+        None
+    };
+    let moved = transfers.len();
+    for (file, destination) in transfers {
+        // Moving keeps the file's contents and metadata without copying them.
+        replace_file(file, &destination, database).await?;
+    }
+    Ok(moved)
+}
+
+/// Returns the files a copy or move from `source` to `destination` affects,
+/// paired with the path each file is transferred to.
+async fn plan_transfers<C: AsyncConnection + Clone + Unpin + 'static>(
+    source: &str,
+    destination: &str,
+    database: &C,
+) -> Result<Vec<(File<Async<C>, DossierFiles>, String)>, HandlerError<ApiError>> {
+    if !source.starts_with('/') || !destination.starts_with('/') {
+        return Err(HandlerError::Api(ApiError::InvalidPath));
+    }
+
+    if source.ends_with('/') {
+        if !destination.ends_with('/') {
+            return Err(HandlerError::Api(ApiError::InvalidPath));
+        } else if destination.starts_with(source) || source.starts_with(destination) {
+            return Err(HandlerError::Api(ApiError::OverlappingPaths));
+        }
+
+        let files = DossierFiles::list_recursive_async(source, database).await?;
+        if files.is_empty() {
+            return Err(HandlerError::Api(ApiError::NotFound));
+        }
+        Ok(files
+            .into_iter()
+            .map(|file| {
+                let path = file.path();
+                let destination = format!("{destination}{}", &path[source.len()..]);
+                (file, destination)
+            })
+            .collect())
+    } else {
+        let file = DossierFiles::load_async(source, database)
+            .await
+            .map_files_error()?
+            .ok_or(HandlerError::Api(ApiError::NotFound))?;
+        let destination = if destination.ends_with('/') {
+            format!("{destination}{}", file.name())
+        } else {
+            destination.to_string()
+        };
+        if destination == source {
+            return Err(HandlerError::Api(ApiError::OverlappingPaths));
+        }
+        Ok(vec![(file, destination)])
+    }
+}
+
+/// Checks that the files in `transfers` fit within the quota of the project
+/// they are transferred to.
+async fn check_transfer_quota<C: AsyncConnection + Clone + Unpin + 'static>(
+    transfers: &mut [(File<Async<C>, DossierFiles>, String)],
+    database: &C,
+) -> Result<(), HandlerError<ApiError>> {
+    let mut writes = Vec::with_capacity(transfers.len());
+    for (file, destination) in transfers {
+        writes.push((destination.clone(), file.len().await?));
+    }
+    check_quota(&writes, None, database).await
+}
+
+/// Creates an alias, or repoints an existing alias, so that the folder `path`
+/// serves the contents of the folder `target`.
+#[derive(Serialize, Deserialize, Debug, Api)]
+#[api(name = "set-alias", response = (), error = ApiError)]
+pub struct SetAlias {
+    pub path: String,
+    pub target: String,
+    pub redirect: bool,
+}
+
+#[async_trait]
+impl Handler<CliBackend, SetAlias> for DossierApiHandler {
+    async fn handle(
+        session: HandlerSession<'_, CliBackend>,
+        request: SetAlias,
+    ) -> HandlerResult<SetAlias> {
+        let result = handle_transfer_op_with_permissions(
+            session,
+            &request.target,
+            &request.path,
+            &request,
+            |database, request| async move {
+                set_alias(&request.path, &request.target, request.redirect, &database).await
+            },
+        )
+        .await;
+        metrics().record_api_request("set-alias", result)
+    }
+}
+
+pub async fn set_alias<C: AsyncConnection>(
+    path: &str,
+    target: &str,
+    redirect: bool,
+    database: &C,
+) -> HandlerResult<SetAlias> {
+    // Aliases must be folders inside of a project.
+    if !path.ends_with('/')
+        || !target.ends_with('/')
+        || path.matches('/').count() < 3
+        || !target.starts_with('/')
+    {
+        return Err(HandlerError::Api(ApiError::InvalidPath));
+    } else if path.starts_with(target) || target.starts_with(path) {
+        return Err(HandlerError::Api(ApiError::OverlappingPaths));
+    }
+
+    // Repointing updates a single document, so requests are served from
+    // either the old or the new target, never a mix of both.
+    match Alias::load_async(path, database).await? {
+        Some(mut alias) => {
+            alias.contents.target = target.to_string();
+            alias.contents.redirect = redirect;
+            alias.update_async(database).await?;
+        }
+        None => {
+            Alias {
+                path: path.to_string(),
+                target: target.to_string(),
+                redirect,
+            }
+            .push_into_async(database)
+            .await?;
+        }
+    }
+    invalidate_aliases();
+    Ok(())
+}
+
+/// Removes the alias at `path`, returning whether it existed.
+#[derive(Serialize, Deserialize, Debug, Api)]
+#[api(name = "delete-alias", response = bool, error = ApiError)]
+pub struct DeleteAlias {
+    pub path: String,
+}
+
+#[async_trait]
+impl Handler<CliBackend, DeleteAlias> for DossierApiHandler {
+    async fn handle(
+        session: HandlerSession<'_, CliBackend>,
+        request: DeleteAlias,
+    ) -> HandlerResult<DeleteAlias> {
+        let result = handle_sync_op_with_permissions(
+            session,
+            &request.path,
+            &request,
+            |database, request| async move { delete_alias(&request.path, &database).await },
+        )
+        .await;
+        metrics().record_api_request("delete-alias", result)
+    }
+}
+
+pub async fn delete_alias<C: AsyncConnection>(
+    path: &str,
+    database: &C,
+) -> HandlerResult<DeleteAlias> {
+    match Alias::load_async(path, database).await? {
+        Some(alias) => {
+            alias.delete_async(database).await?;
+            invalidate_aliases();
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+/// How long cached aliases are used before being reloaded. Changes made
+/// through the API are seen immediately, but aliases written directly to the
+/// database, such as by restoring an archive, are only seen once this passes.
+const ALIAS_CACHE_TTL: Duration = Duration::from_secs(10);
+
+/// Every alias, keyed by path. The webserver consults this for each request,
+/// so it is kept in memory rather than queried each time.
+struct AliasCache {
+    /// Incremented whenever an alias changes, so that a load that raced with
+    /// the change isn't cached.
+    generation: AtomicU64,
+    aliases: RwLock<Option<(Instant, Arc<HashMap<String, Alias>>)>>,
+}
+
+fn alias_cache() -> &'static AliasCache {
+    static CACHE: OnceLock<AliasCache> = OnceLock::new();
+    CACHE.get_or_init(|| AliasCache {
+        generation: AtomicU64::new(0),
+        aliases: RwLock::new(None),
+    })
+}
+
+/// Returns every alias keyed by path, only loading them from `database` when
+/// they have changed or [`ALIAS_CACHE_TTL`] has passed since they were loaded.
+pub(crate) async fn cached_aliases<C: AsyncConnection>(
+    database: &C,
+) -> Result<Arc<HashMap<String, Alias>>, bonsaidb::core::Error> {
+    let cache = alias_cache();
+    if let Some((loaded_at, aliases)) = &*cache.aliases.read() {
+        if loaded_at.elapsed() < ALIAS_CACHE_TTL {
+            return Ok(aliases.clone());
+        }
+    }
+
+    let generation = cache.generation.load(Ordering::SeqCst);
+    let aliases = Arc::new(
+        Alias::all_async(database)
+            .await?
+            .into_iter()
+            .map(|alias| (alias.contents.path.clone(), alias.contents))
+            .collect::<HashMap<_, _>>(),
+    );
+    let mut cached = cache.aliases.write();
+    if cache.generation.load(Ordering::SeqCst) == generation {
+        *cached = Some((Instant::now(), aliases.clone()));
+    }
+    Ok(aliases)
+}
+
+/// Discards the cached aliases. This must be called after any alias changes.
+fn invalidate_aliases() {
+    let cache = alias_cache();
+    cache.generation.fetch_add(1, Ordering::SeqCst);
+    *cache.aliases.write() = None;
+}
+
+/// Lists the aliases within a project. `project` is the project's folder,
+/// e.g. `/bonsaidb/`.
+#[derive(Serialize, Deserialize, Debug, Api)]
+#[api(name = "list-aliases", response = Vec<Alias>, error = ApiError)]
+pub struct ListAliases {
+    pub project: String,
+}
+
+#[async_trait]
+impl Handler<CliBackend, ListAliases> for DossierApiHandler {
+    async fn handle(
+        session: HandlerSession<'_, CliBackend>,
+        request: ListAliases,
+    ) -> HandlerResult<ListAliases> {
+        let result = handle_sync_op_with_permissions(
+            session,
+            &request.project,
+            &request,
+            |database, request| async move { list_aliases(&request.project, &database).await },
+        )
+        .await;
+        metrics().record_api_request("list-aliases", result)
+    }
+}
+
+pub async fn list_aliases<C: AsyncConnection>(
+    project: &str,
+    database: &C,
+) -> HandlerResult<ListAliases> {
+    if !project.ends_with('/') {
+        return Err(HandlerError::Api(ApiError::InvalidPath));
+    }
+
+    let mut aliases = Alias::all_async(database)
+        .await?
+        .into_iter()
+        .map(|alias| alias.contents)
+        .filter(|alias| alias.path.starts_with(project))
+        .collect::<Vec<_>>();
+    aliases.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(aliases)
+}
+
+/// Reports the storage used by the folder `path` and the folders within it, up
+/// to `depth` levels deep.
+#[derive(Serialize, Deserialize, Debug, Api)]
+#[api(name = "usage", response = Vec<FolderUsage>, error = ApiError)]
+pub struct GetUsage {
+    pub path: String,
+    pub depth: usize,
+}
+
+#[async_trait]
+impl Handler<CliBackend, GetUsage> for DossierApiHandler {
+    async fn handle(
+        session: HandlerSession<'_, CliBackend>,
+        request: GetUsage,
+    ) -> HandlerResult<GetUsage> {
+        let result = handle_sync_op_with_permissions(
+            session,
+            &request.path,
+            &request,
+            |database, request| async move {
+                folder_usage(&request.path, request.depth, &database).await
+            },
+        )
+        .await;
+        metrics().record_api_request("usage", result)
+    }
+}
+
+pub async fn folder_usage<C: AsyncConnection + Clone + Unpin + 'static>(
+    path: &str,
+    depth: usize,
+    database: &C,
+) -> HandlerResult<GetUsage> {
+    if !path.starts_with('/') || !path.ends_with('/') {
+        return Err(HandlerError::Api(ApiError::InvalidPath));
+    }
+
+    let mut files = Vec::new();
+    for mut file in DossierFiles::list_recursive_async(path, database).await? {
+        files.push(FileUsage {
+            path: file.path(),
+            len: file.len().await?,
+            blake3: file.metadata().map(|metadata| metadata.blake3),
+        });
+    }
+    Ok(usage::summarize(path, depth, &files))
+}
+
+/// Reports the version, uptime, and storage size of the server, along with its
+/// number of projects and files and its most recent compaction.
+#[derive(Serialize, Deserialize, Debug, Api)]
+#[api(name = "server-status", response = ServerStatus, error = ApiError)]
+pub struct GetServerStatus;
+
+#[async_trait]
+impl Handler<CliBackend, GetServerStatus> for DossierApiHandler {
+    async fn handle(
+        session: HandlerSession<'_, CliBackend>,
+        _request: GetServerStatus,
+    ) -> HandlerResult<GetServerStatus> {
+        let result = server_status(&session).await;
+        metrics().record_api_request("server-status", result)
+    }
+}
+
+async fn server_status(session: &HandlerSession<'_, CliBackend>) -> HandlerResult<GetServerStatus> {
+    session
+        .as_client
+        .check_permission(status_resource_name(), &DossierAction::ViewStatus)?;
+    // Viewing the status doesn't require being allowed to read the database,
+    // so it is read with the server's permissions.
+    let database = session.server.database::<Dossier>("dossier").await?;
+    database_status(Some(status::uptime()), &database).await
+}
+
+/// Returns the status of the server that stores `database`. `uptime` is `None`
+/// when the database was opened locally rather than by a running server.
+pub async fn database_status<C: AsyncConnection + Clone + Unpin + 'static>(
+    uptime: Option<Duration>,
+    database: &C,
+) -> HandlerResult<GetServerStatus> {
+    let stats = DossierFiles::stats_for_path_async("/", database).await?;
+    Ok(ServerStatus {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        uptime_secs: uptime.map(|uptime| uptime.as_secs()),
+        storage_bytes: status::disk_usage(Path::new(status::STORAGE_PATH)).map_err(io_error)?,
+        project_count: Project::all_async(database).count().await?,
+        file_count: stats.file_count,
+        file_bytes: stats.total_bytes,
+        last_compaction: compactor::last_compaction(database).await?,
+    })
+}
+
+/// The folder that files are written to while they are being uploaded. Paths
+/// beginning with `/_` are never served by the webserver, and project slugs
+/// can't begin with `_`.
+pub const UPLOADS_FOLDER: &str = "/_uploads/";
+
+/// Returns a new, unique path in [`UPLOADS_FOLDER`]. The name begins with the
+/// time it was created, which [`staged_at`] returns.
+fn staged_upload_path() -> String {
+    format!(
+        "{UPLOADS_FOLDER}{:x}-{:016x}",
+        unix_timestamp(),
+        rand::random::<u64>()
+    )
+}
+
+/// Returns when the staged file named `name` was created, in seconds since the
+/// Unix epoch. Returns `None` for names without a timestamp, such as staged
+/// files from `WriteFileData`.
+pub(crate) fn staged_at(name: &str) -> Option<u64> {
+    let (timestamp, _) = name.split_once('-')?;
+    u64::from_str_radix(timestamp, 16).ok()
+}
+
+/// Moves a completed upload over the file at `path`.
+async fn replace_file<C: AsyncConnection + Clone + Unpin + 'static>(
+    mut uploaded: File<Async<C>, DossierFiles>,
+    path: &str,
+    database: &C,
+) -> Result<(), HandlerError<ApiError>> {
+    if !matches!(path.rsplit_once('/'), Some((_, name)) if !name.is_empty()) {
+        return Err(HandlerError::Api(ApiError::InvalidPath));
+    }
+
+    // Files can't be moved over an existing file, so the old file is deleted
+    // first. Uploads and copies stay in `UPLOADS_FOLDER` until then, so no
+    // temporary file is ever visible alongside the project's files. If the
+    // final move fails, a staged file is garbage-collected and a moved file is
+    // left at its source.
+    DossierFiles::delete_async(path, database)
+        .await
+        .map_files_error()?;
+    uploaded.move_to(path).await.map_files_error()
+}
+
+/// Returns the lock held while checking a project's quota and reserving space
+/// for an upload or copying files, so that concurrent writes can't each fit
+/// within a quota that they exceed together.
+fn quota_lock() -> &'static futures::lock::Mutex<()> {
+    static LOCK: OnceLock<futures::lock::Mutex<()>> = OnceLock::new();
+    LOCK.get_or_init(|| futures::lock::Mutex::new(()))
+}
+
+/// Checks that `writes`, each a file of some length replacing any file at its
+/// path, can be made without exceeding the quotas of their projects.
+///
+/// Uploads in progress count towards their project's usage with the length
+/// they declared, except for the upload session `skip_session`, whose write
+/// is one of `writes`.
+async fn check_quota<C: AsyncConnection + Clone + Unpin + 'static>(
+    writes: &[(String, u64)],
+    skip_session: Option<u64>,
+    database: &C,
+) -> Result<(), HandlerError<ApiError>> {
+    let mut projects = HashMap::<&str, Vec<(&str, u64)>>::new();
+    for (path, len) in writes {
+        let Some(slug) = path.split('/').nth(1) else {
+            return Err(HandlerError::Api(ApiError::InvalidPath));
+        };
+        projects.entry(slug).or_default().push((path, *len));
+    }
+
+    for (slug, writes) in projects {
+        let quota = Project::load_async(slug, database)
+            .await?
+            .ok_or(HandlerError::Api(ApiError::ProjectNotFound))?
+            .contents
+            .quota;
+
+        if quota
+            .max_file_size
+            .is_some_and(|max| writes.iter().any(|(_, len)| *len > max))
+        {
+            return Err(HandlerError::Api(ApiError::QuotaExceeded(Quota::FileSize)));
+        }
+        if quota.max_bytes.is_none() && quota.max_files.is_none() {
+            continue;
+        }
+
+        let usage = DossierFiles::stats_for_path_async(&format!("/{slug}/"), database).await?;
+        let sessions = UploadSession::all_async(database).await?;
+        let uploading = sessions
+            .iter()
+            .filter(|upload| {
+                Some(upload.header.id) != skip_session
+                    && upload.contents.path.split('/').nth(1) == Some(slug)
+            })
+            .map(|upload| (upload.contents.path.as_str(), upload.contents.len));
+
+        // Each write replaces any file at its path, so only the difference in
+        // length counts towards the project's usage.
+        let mut bytes = usage.total_bytes;
+        let mut files = usage.file_count;
+        for (path, len) in writes.iter().copied().chain(uploading) {
+            match DossierFiles::load_async(path, database)
+                .await
+                .map_files_error()?
+            {
+                Some(mut existing) => bytes = bytes.saturating_sub(existing.len().await?),
+                None => files += 1,
+            }
+            bytes += len;
+        }
+
+        if quota.max_bytes.is_some_and(|max| bytes > max) {
+            return Err(HandlerError::Api(ApiError::QuotaExceeded(Quota::Bytes)));
+        } else if quota.max_files.is_some_and(|max| files > max) {
+            return Err(HandlerError::Api(ApiError::QuotaExceeded(Quota::Files)));
+        }
+    }
+    Ok(())
+}
+
+pub(crate) async fn compute_hash<C: AsyncConnection + Clone + Unpin + 'static>(
+    file: &mut File<Async<C>, DossierFiles>,
+) -> Result<[u8; 32], HandlerError<ApiError>> {
+    let mut contents = file.contents().await?;
+    let mut sha = blake3::Hasher::new();
+    while let Some(block) = contents.next().await {
+        let block = block?;
+        sha.update(&block);
+    }
+
+    Ok(sha.finalize().into())
+}
+
+pub(crate) fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Superseded by upload sessions ([`StartUpload`]), but still supported for
+/// older clients.
+#[derive(Serialize, Deserialize, Debug, Api)]
+#[api(name = "write-file", response = Option<Bytes>, error = ApiError)]
+pub struct WriteFileData {
+    pub path: String,
+    pub data: Bytes,
+    pub start: bool,
+    pub finished: bool,
+}
+
+#[async_trait]
+impl Handler<CliBackend, WriteFileData> for DossierApiHandler {
+    async fn handle(
+        session: HandlerSession<'_, CliBackend>,
+        request: WriteFileData,
+    ) -> HandlerResult<WriteFileData> {
+        let result = handle_sync_op_with_permissions(
+            session,
+            &request.path,
+            &request,
+            |database, request| async move {
+                write_file_data(
+                    &request.path,
+                    &request.data,
+                    request.start,
+                    request.finished,
+                    &database,
+                )
+                .await
+            },
+        )
+        .await;
+        metrics().record_api_request("write-file", result)
+    }
+}
+
+pub async fn write_file_data<C: AsyncConnection + Clone + Unpin + 'static>(
+    path: &str,
+    data: &[u8],
+    start: bool,
+    finished: bool,
+    database: &C,
+) -> HandlerResult<WriteFileData> {
+    // This API has no session, so the upload is staged at a path derived from
+    // its destination.
+    let staged_path = format!(
+        "{UPLOADS_FOLDER}write-file-{}",
+        blake3::hash(path.as_bytes()).to_hex()
+    );
+    let mut file = match DossierFiles::load_async(&staged_path, database)
+        .await
+        .map_files_error()?
+    {
+        Some(file) if start => {
+            file.truncate(0, Truncate::RemovingStart).await?;
+            file
+        }
+        Some(file) => file,
+        None if start => DossierFiles::build(&staged_path)
+            .create_async(database)
+            .await
+            .map_files_error()?,
+        None => return Err(HandlerError::Api(ApiError::Deleted)),
+    };
+
+    let len = file.len().await? + data.len() as u64;
+    let session_id = upload_session_id(&file);
+    let upload = UploadSession::get_async(&session_id, database).await?;
+    // Restarting an upload discards what it had reserved.
+    let reserved = match &upload {
+        Some(upload) if !start => upload.contents.len,
+        _ => 0,
+    };
+
+    // This API doesn't declare the file's length up front, so the upload's
+    // session reserves space in the project's quota as it grows. Checking the
+    // quota scans the project, so each check reserves twice the length written
+    // so far, falling back to the exact length if that doesn't fit. Chunks that
+    // fit within the reservation, which was checked against the maximum file
+    // size and the project's quota, need no further checks.
+    let (guard, reserved) = if finished || len > reserved {
+        let guard = quota_lock().lock().await;
+        let doubled = len.saturating_mul(2);
+        let reserved = if !finished
+            && check_quota(&[(path.to_string(), doubled)], Some(session_id), database)
+                .await
+                .is_ok()
+        {
+            doubled
+        } else {
+            check_quota(&[(path.to_string(), len)], Some(session_id), database).await?;
+            len
+        };
+        (Some(guard), reserved)
+    } else {
+        (None, reserved)
+    };
+
+    // Tracking the upload with a session allows it to expire like uploads from
+    // `StartUpload` if it is abandoned.
+    let upload = match upload {
+        Some(mut upload) => {
+            upload.contents.len = reserved;
+            upload.contents.last_activity = unix_timestamp();
+            upload.update_async(database).await?;
+            upload
+        }
+        None => UploadSession {
+            path: path.to_string(),
+            file_id: file.id(),
+            len: reserved,
+            last_activity: unix_timestamp(),
+        }
+        .insert_into_async(&session_id, database)
+        .await
+        .map_err(|err| err.error)?,
+    };
+    drop(guard);
+
+    file.append(data).await?;
+
+    if finished {
+        let hash = compute_hash(&mut file).await?;
+        *file.metadata_mut() = Some(Metadata {
+            blake3: hash,
+            written_at: unix_timestamp(),
+        });
+        file.update_metadata().await?;
+        replace_file(file, path, database).await?;
+        upload.delete_async(database).await?;
+
+        Ok(Some(Bytes::from(hash.to_vec())))
+    } else {
+        Ok(None)
     }
 }
 
@@ -228,16 +1441,480 @@ async fn handle_sync_op_with_permissions<
     handler: Handle,
 ) -> HandlerResult<A> {
     let database = session.as_client.database::<Dossier>("dossier").await?;
+    check_sync_permission(&session, path, &database).await?;
+    handler(database, request).await
+}
+
+/// Checks that the session's client is allowed to sync files in the project
+/// that `path` belongs to.
+async fn check_sync_permission(
+    session: &HandlerSession<'_, CliBackend>,
+    path: &str,
+    database: &ServerDatabase<CliBackend>,
+) -> Result<(), HandlerError<ApiError>> {
     let project = path.split('/').nth(1);
     if let Some(project) = project {
-        if let Some(project) = Project::load_async(project, &database).await? {
+        if let Some(project) = Project::load_async(project, database).await? {
             session.as_client.check_permission(
                 project_resource_name(project.header.id),
                 &DossierAction::SyncFiles,
             )?;
 
-            return handler(database, request).await;
+            return Ok(());
         }
     }
     Err(HandlerError::Api(ApiError::ProjectNotFound))
 }
+
+/// Checks that the session's client is allowed to sync files in the projects of
+/// both `source` and `destination`.
+async fn handle_transfer_op_with_permissions<
+    'future,
+    A: Api<Error = ApiError>,
+    Handle: FnOnce(ServerDatabase<CliBackend>, &'future A) -> F,
+    F: Future<Output = HandlerResult<A>> + 'future,
+>(
+    session: HandlerSession<'_, CliBackend>,
+    source: &str,
+    destination: &str,
+    request: &'future A,
+    handler: Handle,
+) -> HandlerResult<A> {
+    let database = session.as_client.database::<Dossier>("dossier").await?;
+    check_sync_permission(&session, destination, &database).await?;
+    handle_sync_op_with_permissions(session, source, request, handler).await
+}
+
+/// Loads the upload session `session_id` and checks that the session's client
+/// is allowed to sync files to the project the upload targets.
+async fn handle_upload_op_with_permissions<
+    'future,
+    A: Api<Error = ApiError>,
+    Handle: FnOnce(ServerDatabase<CliBackend>, CollectionDocument<UploadSession>, &'future A) -> F,
+    F: Future<Output = HandlerResult<A>> + 'future,
+>(
+    session: HandlerSession<'_, CliBackend>,
+    session_id: u64,
+    request: &'future A,
+    handler: Handle,
+) -> HandlerResult<A> {
+    let database = session.as_client.database::<Dossier>("dossier").await?;
+    let upload = load_upload_session(session_id, &database).await?;
+    let path = upload.contents.path.clone();
+    handle_sync_op_with_permissions(session, &path, request, |database, request| {
+        handler(database, upload, request)
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use bonsaidb::{
+        core::{
+            connection::AsyncLowLevelConnection,
+            transaction::{Operation, Transaction},
+        },
+        local::{
+            config::{Builder, StorageConfiguration},
+            AsyncDatabase,
+        },
+    };
+
+    use super::*;
+    use crate::{gc, schema::ProjectQuota};
+
+    /// A database containing the project `project`, which is removed when
+    /// dropped.
+    struct TestDatabase {
+        database: AsyncDatabase,
+        path: PathBuf,
+    }
+
+    impl TestDatabase {
+        async fn new(quota: ProjectQuota) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "dossier-api-test-{:016x}.bonsaidb",
+                rand::random::<u64>()
+            ));
+            let database = AsyncDatabase::open::<Dossier>(StorageConfiguration::new(&path))
+                .await
+                .unwrap();
+            Project {
+                slug: String::from("project"),
+                quota,
+            }
+            .push_into_async(&database)
+            .await
+            .unwrap();
+            Self { database, path }
+        }
+    }
+
+    impl Drop for TestDatabase {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.path);
+        }
+    }
+
+    async fn send_chunk(
+        session_id: u64,
+        offset: u64,
+        data: &[u8],
+        database: &AsyncDatabase,
+    ) -> Result<UploadStatus, HandlerError<ApiError>> {
+        let upload = load_upload_session(session_id, database).await?;
+        upload_chunk(upload, offset, data, database).await
+    }
+
+    async fn finish(
+        session_id: u64,
+        database: &AsyncDatabase,
+    ) -> Result<Bytes, HandlerError<ApiError>> {
+        let upload = load_upload_session(session_id, database).await?;
+        finish_upload(upload, database).await
+    }
+
+    async fn contents(path: &str, database: &AsyncDatabase) -> Vec<u8> {
+        read_file(path, 0, MAX_READ_LEN, database)
+            .await
+            .unwrap()
+            .to_vec()
+    }
+
+    #[tokio::test]
+    async fn upload_session() {
+        let test = TestDatabase::new(ProjectQuota::default()).await;
+        let database = &test.database;
+
+        let upload = start_upload("/project/file", 11, database).await.unwrap();
+        assert_eq!(upload.offset, 0);
+        let status = send_chunk(upload.session_id, 0, b"hello ", database)
+            .await
+            .unwrap();
+        assert_eq!(status.offset, 6);
+
+        // A retried chunk is rejected, and the upload resumes from the offset
+        // the server reports.
+        let retried = send_chunk(upload.session_id, 0, b"hello ", database).await;
+        assert!(matches!(
+            retried,
+            Err(HandlerError::Api(ApiError::UploadOffsetMismatch {
+                acknowledged: 6
+            }))
+        ));
+        let session = load_upload_session(upload.session_id, database)
+            .await
+            .unwrap();
+        let status = upload_status(session, database).await.unwrap();
+        assert_eq!(status.offset, 6);
+        send_chunk(upload.session_id, status.offset, b"world", database)
+            .await
+            .unwrap();
+
+        // Nothing is visible at the path until the upload finishes.
+        assert!(stat_file("/project/file", database)
+            .await
+            .unwrap()
+            .is_none());
+        let hash = finish(upload.session_id, database).await.unwrap();
+        assert_eq!(&hash[..], blake3::hash(b"hello world").as_bytes());
+        assert_eq!(contents("/project/file", database).await, b"hello world");
+
+        // Finishing removes the session.
+        assert!(matches!(
+            load_upload_session(upload.session_id, database).await,
+            Err(HandlerError::Api(ApiError::UploadNotFound))
+        ));
+    }
+
+    #[tokio::test]
+    async fn empty_upload() {
+        let test = TestDatabase::new(ProjectQuota::default()).await;
+        let database = &test.database;
+
+        let upload = start_upload("/project/empty", 0, database).await.unwrap();
+        let hash = finish(upload.session_id, database).await.unwrap();
+        assert_eq!(&hash[..], blake3::hash(b"").as_bytes());
+        let file = stat_file("/project/empty", database)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(file.len, 0);
+        assert_eq!(file.blake3, Some(*blake3::hash(b"").as_bytes()));
+    }
+
+    #[tokio::test]
+    async fn expired_upload() {
+        let test = TestDatabase::new(ProjectQuota::default()).await;
+        let database = &test.database;
+
+        let upload = start_upload("/project/file", 14, database).await.unwrap();
+        send_chunk(upload.session_id, 0, b"partial", database)
+            .await
+            .unwrap();
+        let mut session = load_upload_session(upload.session_id, database)
+            .await
+            .unwrap();
+        session.contents.last_activity = 0;
+        session.update_async(database).await.unwrap();
+
+        let collected = gc::collect(database, 60, false).await.unwrap();
+        assert_eq!(collected.expired_uploads, 1);
+        assert_eq!(collected.bytes, 7);
+        assert!(matches!(
+            send_chunk(upload.session_id, 7, b" upload", database).await,
+            Err(HandlerError::Api(ApiError::UploadNotFound))
+        ));
+        assert!(matches!(
+            finish(upload.session_id, database).await,
+            Err(HandlerError::Api(ApiError::UploadNotFound))
+        ));
+        assert!(DossierFiles::list_recursive_async(UPLOADS_FOLDER, database)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn upload_over_quota() {
+        let test = TestDatabase::new(ProjectQuota {
+            max_bytes: Some(10),
+            max_files: Some(2),
+            max_file_size: Some(8),
+        })
+        .await;
+        let database = &test.database;
+
+        assert!(matches!(
+            start_upload("/project/big", 9, database).await,
+            Err(HandlerError::Api(ApiError::QuotaExceeded(Quota::FileSize)))
+        ));
+
+        // Chunks can't write more than the upload declared.
+        let upload = start_upload("/project/a", 6, database).await.unwrap();
+        assert!(matches!(
+            send_chunk(upload.session_id, 0, b"1234567", database).await,
+            Err(HandlerError::Api(ApiError::UploadTooLong { declared: 6 }))
+        ));
+
+        // The unfinished upload counts towards the project's usage, as does
+        // the file once it finishes.
+        assert!(matches!(
+            start_upload("/project/b", 6, database).await,
+            Err(HandlerError::Api(ApiError::QuotaExceeded(Quota::Bytes)))
+        ));
+        send_chunk(upload.session_id, 0, b"123456", database)
+            .await
+            .unwrap();
+        finish(upload.session_id, database).await.unwrap();
+        assert!(matches!(
+            start_upload("/project/b", 6, database).await,
+            Err(HandlerError::Api(ApiError::QuotaExceeded(Quota::Bytes)))
+        ));
+
+        // Replacing a file only counts the difference in length.
+        start_upload("/project/a", 8, database).await.unwrap();
+        start_upload("/project/b", 2, database).await.unwrap();
+        assert!(matches!(
+            start_upload("/project/c", 0, database).await,
+            Err(HandlerError::Api(ApiError::QuotaExceeded(Quota::Files)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn concurrent_uploads_over_quota() {
+        let test = TestDatabase::new(ProjectQuota {
+            max_bytes: Some(10),
+            ..ProjectQuota::default()
+        })
+        .await;
+        let database = &test.database;
+
+        let (a, b) = futures::join!(
+            start_upload("/project/a", 6, database),
+            start_upload("/project/b", 6, database)
+        );
+        // Only one of the uploads fits within the quota.
+        let results = [a, b];
+        assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 1);
+        assert!(results.iter().any(|result| matches!(
+            result,
+            Err(HandlerError::Api(ApiError::QuotaExceeded(Quota::Bytes)))
+        )));
+    }
+
+    #[tokio::test]
+    async fn write_file_data_over_quota() {
+        let test = TestDatabase::new(ProjectQuota {
+            max_bytes: Some(10),
+            max_files: None,
+            max_file_size: Some(8),
+        })
+        .await;
+        let database = &test.database;
+
+        // Every chunk is limited to the space reserved for the write, not only
+        // the first and last chunks.
+        write_file_data("/project/a", b"1234", true, false, database)
+            .await
+            .unwrap();
+        write_file_data("/project/a", b"5678", false, false, database)
+            .await
+            .unwrap();
+        assert!(matches!(
+            write_file_data("/project/a", b"9", false, false, database).await,
+            Err(HandlerError::Api(ApiError::QuotaExceeded(Quota::FileSize)))
+        ));
+
+        // The space reserved by the unfinished write counts towards the
+        // project's usage.
+        assert!(matches!(
+            write_file_data("/project/b", b"123", true, false, database).await,
+            Err(HandlerError::Api(ApiError::QuotaExceeded(Quota::Bytes)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn transfers_over_quota() {
+        let test = TestDatabase::new(ProjectQuota::default()).await;
+        let database = &test.database;
+        Project {
+            slug: String::from("limited"),
+            quota: ProjectQuota {
+                max_bytes: Some(10),
+                ..ProjectQuota::default()
+            },
+        }
+        .push_into_async(database)
+        .await
+        .unwrap();
+        for (path, data) in [
+            ("/project/a/1", &b"123456"[..]),
+            ("/project/a/2", b"123456"),
+        ] {
+            let upload = start_upload(path, data.len() as u64, database)
+                .await
+                .unwrap();
+            send_chunk(upload.session_id, 0, data, database)
+                .await
+                .unwrap();
+            finish(upload.session_id, database).await.unwrap();
+        }
+
+        // The folder's 12 bytes don't fit in the destination project.
+        for result in [
+            copy_files("/project/a/", "/limited/", database).await,
+            move_files("/project/a/", "/limited/", database).await,
+        ] {
+            assert!(matches!(
+                result,
+                Err(HandlerError::Api(ApiError::QuotaExceeded(Quota::Bytes)))
+            ));
+        }
+        assert!(list_remote_files("/limited/", database)
+            .await
+            .unwrap()
+            .is_empty());
+
+        // A single file does.
+        assert_eq!(
+            copy_files("/project/a/1", "/limited/", database)
+                .await
+                .unwrap(),
+            1
+        );
+        assert!(matches!(
+            move_files("/project/a/2", "/limited/2", database).await,
+            Err(HandlerError::Api(ApiError::QuotaExceeded(Quota::Bytes)))
+        ));
+        // Replacing the copy only counts the difference in length.
+        assert_eq!(
+            move_files("/project/a/2", "/limited/1", database)
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(contents("/limited/1", database).await, b"123456");
+    }
+
+    #[tokio::test]
+    async fn replace_existing_file() {
+        let test = TestDatabase::new(ProjectQuota::default()).await;
+        let database = &test.database;
+
+        for data in [&b"old"[..], b"new"] {
+            let upload = start_upload("/project/file", data.len() as u64, database)
+                .await
+                .unwrap();
+            send_chunk(upload.session_id, 0, data, database)
+                .await
+                .unwrap();
+            finish(upload.session_id, database).await.unwrap();
+        }
+
+        assert_eq!(contents("/project/file", database).await, b"new");
+        // Nothing but the replaced file is left in the project.
+        let files = list_remote_files("/project/", database).await.unwrap();
+        assert_eq!(
+            files
+                .iter()
+                .map(|file| file.path.as_str())
+                .collect::<Vec<_>>(),
+            ["/project/file"]
+        );
+    }
+
+    #[tokio::test]
+    async fn orphaned_blocks() {
+        let test = TestDatabase::new(ProjectQuota::default()).await;
+        let database = &test.database;
+
+        let upload = start_upload("/project/file", 4, database).await.unwrap();
+        send_chunk(upload.session_id, 0, b"kept", database)
+            .await
+            .unwrap();
+        finish(upload.session_id, database).await.unwrap();
+        assert_eq!(gc::collect_blocks(database, false).await.unwrap().blocks, 0);
+
+        // A block left behind by a file that was deleted.
+        let mut block = b"orphaned".to_vec();
+        block.extend(0_u64.to_be_bytes());
+        block.extend(u32::MAX.to_be_bytes());
+        let mut transaction = Transaction::new();
+        transaction.push(Operation::insert(DossierFiles::blocks_name(), None, block));
+        database.apply_transaction(transaction).await.unwrap();
+
+        let collected = gc::collect_blocks(database, true).await.unwrap();
+        assert_eq!(collected.blocks, 1);
+        assert_eq!(collected.bytes, 8);
+        gc::collect_blocks(database, false).await.unwrap();
+        assert_eq!(gc::collect_blocks(database, true).await.unwrap().blocks, 0);
+        assert_eq!(contents("/project/file", database).await, b"kept");
+    }
+
+    #[tokio::test]
+    async fn write_file_data_session() {
+        let test = TestDatabase::new(ProjectQuota::default()).await;
+        let database = &test.database;
+
+        for (data, start, finished) in [(&b"one "[..], true, false), (b"two ", false, false)] {
+            let hash = write_file_data("/project/file", data, start, finished, database)
+                .await
+                .unwrap();
+            assert!(hash.is_none());
+            // Each write reuses the session created by the first.
+            assert_eq!(UploadSession::all_async(database).await.unwrap().len(), 1);
+        }
+        let hash = write_file_data("/project/file", b"three", false, true, database)
+            .await
+            .unwrap();
+        assert_eq!(
+            hash.as_deref().map(|hash| &hash[..]),
+            Some(&blake3::hash(b"one two three").as_bytes()[..])
+        );
+        assert_eq!(contents("/project/file", database).await, b"one two three");
+        assert!(UploadSession::all_async(database).await.unwrap().is_empty());
+    }
+}
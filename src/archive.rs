@@ -0,0 +1,1231 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use bonsaidb::{
+    core::{connection::AsyncConnection, schema::SerializedCollection},
+    files::{
+        direct::{Async, File},
+        FileConfig,
+    },
+};
+use futures::{StreamExt, TryStreamExt};
+use ron::ser::PrettyConfig;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+
+use crate::{
+    api::{compute_hash, unix_timestamp, UPLOADS_FOLDER},
+    schema::{Alias, ApiToken, DossierFiles, Metadata, Project, RetentionRule},
+};
+
+/// The folder within an archive that file contents are stored in, named by
+/// their blake3 hash.
+const BLOBS: &str = "blobs";
+/// The folder within an archive that each snapshot's manifest is stored in.
+const SNAPSHOTS: &str = "snapshots";
+/// The file within an archive that is held while snapshots are created or
+/// pruned. See [`ArchiveLock`].
+const LOCK: &str = "lock";
+
+/// The contents of the database at the time a snapshot was taken. File
+/// contents are stored separately, so that each blob is only stored once
+/// across every snapshot in an archive.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Manifest {
+    /// When the snapshot was taken, in seconds since the Unix epoch. This also
+    /// identifies the snapshot.
+    pub id: u64,
+    pub projects: Vec<(u32, Project)>,
+    pub api_tokens: Vec<(u64, ApiToken)>,
+    #[serde(default)]
+    pub aliases: Vec<(u64, Alias)>,
+    #[serde(default)]
+    pub retention_rules: Vec<(u64, RetentionRule)>,
+    pub files: Vec<ArchivedFile>,
+}
+
+impl Manifest {
+    /// Reads the database's records into a manifest for the snapshot `id`.
+    async fn new<C: AsyncConnection + Clone + Unpin + 'static>(
+        id: u64,
+        files: Vec<ArchivedFile>,
+        database: &C,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            id,
+            projects: Project::all_async(database)
+                .await?
+                .into_iter()
+                .map(|project| (project.header.id, project.contents))
+                .collect(),
+            api_tokens: ApiToken::all_async(database)
+                .await?
+                .into_iter()
+                .map(|token| (token.header.id, token.contents))
+                .collect(),
+            aliases: Alias::all_async(database)
+                .await?
+                .into_iter()
+                .map(|alias| (alias.header.id, alias.contents))
+                .collect(),
+            retention_rules: RetentionRule::all_async(database)
+                .await?
+                .into_iter()
+                .map(|rule| (rule.header.id, rule.contents))
+                .collect(),
+            files,
+        })
+    }
+
+    /// Checks that every file names its blob by a hex-encoded blake3 hash,
+    /// since blob names are used to build paths within the archive.
+    fn validate(&self) -> anyhow::Result<()> {
+        for file in &self.files {
+            if !is_blob_name(&file.blake3) {
+                anyhow::bail!("{} has an invalid hash {:?}", file.path, file.blake3);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct ArchivedFile {
+    pub path: String,
+    pub len: u64,
+    /// The hex-encoded blake3 hash of the file, which names its blob.
+    pub blake3: String,
+    pub written_at: u64,
+}
+
+/// The result of [`create`].
+#[derive(Debug)]
+pub(crate) struct Created {
+    pub id: u64,
+    pub files: usize,
+    /// The number of blobs that weren't already in the archive.
+    pub new_blobs: usize,
+    pub new_bytes: u64,
+    /// Files whose contents didn't match their stored hash. They are archived
+    /// with the hash of their actual contents.
+    pub warnings: Vec<String>,
+}
+
+/// Takes a snapshot of `database` in the archive at `destination`, only
+/// storing file contents that the archive doesn't already contain.
+/// `parallelism` files are read at a time.
+pub(crate) async fn create<C: AsyncConnection + Clone + Unpin + 'static>(
+    database: &C,
+    destination: &Path,
+    parallelism: usize,
+) -> anyhow::Result<Created> {
+    let blobs = destination.join(BLOBS);
+    let snapshots = destination.join(SNAPSHOTS);
+    std::fs::create_dir_all(&blobs)?;
+    std::fs::create_dir_all(&snapshots)?;
+    let _lock = ArchiveLock::acquire(destination)?;
+
+    let files = DossierFiles::list_recursive_async("/", database)
+        .await?
+        .into_iter()
+        // Uploads in progress aren't part of any project.
+        .filter(|file| !file.containing_path().starts_with(UPLOADS_FOLDER));
+    let mut stored = futures::stream::iter(files)
+        .map(|file| store_file(file, &blobs))
+        .buffer_unordered(parallelism)
+        .try_collect::<Vec<_>>()
+        .await?;
+    stored.sort_by(|a, b| a.0.path.cmp(&b.0.path));
+
+    let mut created = Created {
+        id: unix_timestamp(),
+        files: stored.len(),
+        new_blobs: 0,
+        new_bytes: 0,
+        warnings: Vec::new(),
+    };
+    for (file, new, warning) in &mut stored {
+        if *new {
+            created.new_blobs += 1;
+            created.new_bytes += file.len;
+        }
+        created.warnings.extend(warning.take());
+    }
+    // Snapshots taken within the same second are given the next free id.
+    while manifest_path(destination, created.id).exists() {
+        created.id += 1;
+    }
+
+    let manifest = Manifest::new(
+        created.id,
+        stored.into_iter().map(|(file, ..)| file).collect(),
+        database,
+    )
+    .await?;
+    // The manifest is written last, so a snapshot only appears once all of
+    // its blobs are stored.
+    let temporary = snapshots.join(format!(".{}.tmp", created.id));
+    std::fs::write(
+        &temporary,
+        ron::Options::default().to_string_pretty(&manifest, PrettyConfig::default())?,
+    )?;
+    std::fs::rename(&temporary, manifest_path(destination, created.id))?;
+
+    Ok(created)
+}
+
+/// Stores `file`'s contents in `blobs` unless a blob with its hash already
+/// exists, returning the file's manifest entry, whether a blob was stored, and
+/// a warning if the contents didn't match the file's stored hash.
+async fn store_file<C: AsyncConnection + Clone + Unpin + 'static>(
+    mut file: File<Async<C>, DossierFiles>,
+    blobs: &Path,
+) -> anyhow::Result<(ArchivedFile, bool, Option<String>)> {
+    let path = file.path();
+    let metadata = *file.metadata();
+    let written_at = metadata.map_or(0, |metadata| metadata.written_at);
+    if let Some(metadata) = metadata {
+        let blake3 = blake3::Hash::from(metadata.blake3).to_hex().to_string();
+        if blob_path(blobs, &blake3).exists() {
+            return Ok((
+                ArchivedFile {
+                    path,
+                    len: file.len().await?,
+                    blake3,
+                    written_at,
+                },
+                false,
+                None,
+            ));
+        }
+    }
+
+    // The contents are streamed into a temporary file, which is renamed once
+    // its hash is known.
+    let temporary = blobs.join(format!(".{:016x}.tmp", rand::random::<u64>()));
+    let mut output = tokio::fs::File::create(&temporary).await?;
+    let mut hasher = blake3::Hasher::new();
+    let mut len = 0;
+    let mut contents = file.contents().await?;
+    while let Some(block) = contents.next().await {
+        let block = block?;
+        hasher.update(&block);
+        output.write_all(&block).await?;
+        len += block.len() as u64;
+    }
+    output.flush().await?;
+    drop(output);
+
+    let hash = hasher.finalize();
+    // A corrupted file shouldn't keep the rest of the database from being
+    // archived, so its actual contents are stored under their own hash.
+    let warning = metadata
+        .filter(|metadata| hash != metadata.blake3)
+        .map(|_| format!("{path} did not match its hash; archived its current contents"));
+    let blake3 = hash.to_hex().to_string();
+    let destination = blob_path(blobs, &blake3);
+    if let Some(parent) = destination.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    tokio::fs::rename(&temporary, &destination).await?;
+
+    Ok((
+        ArchivedFile {
+            path,
+            len,
+            blake3,
+            written_at,
+        },
+        true,
+        warning,
+    ))
+}
+
+/// Returns the manifests of every snapshot in the archive at `archive`, oldest
+/// first.
+pub(crate) fn list(archive: &Path) -> anyhow::Result<Vec<Manifest>> {
+    let mut manifests = Vec::new();
+    for entry in std::fs::read_dir(archive.join(SNAPSHOTS))? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|extension| extension == "ron") {
+            let manifest = ron::from_str::<Manifest>(&std::fs::read_to_string(&path)?)?;
+            manifest
+                .validate()
+                .map_err(|err| anyhow::anyhow!("{}: {err}", path.display()))?;
+            manifests.push(manifest);
+        }
+    }
+    manifests.sort_by_key(|manifest| manifest.id);
+    Ok(manifests)
+}
+
+/// The result of [`prune`].
+#[derive(Debug, Default)]
+pub(crate) struct Pruned {
+    pub snapshots: usize,
+    pub blobs: usize,
+    pub bytes: u64,
+}
+
+/// Removes all but the newest `keep` snapshots from the archive at `archive`,
+/// along with every blob that the remaining snapshots don't refer to. At least
+/// one snapshot must be kept.
+pub(crate) fn prune(archive: &Path, keep: usize) -> anyhow::Result<Pruned> {
+    if keep == 0 {
+        anyhow::bail!("at least one snapshot must be kept");
+    }
+    // Blobs being stored by `create` aren't referenced by any snapshot yet.
+    let _lock = ArchiveLock::acquire(archive)?;
+    let mut pruned = Pruned::default();
+    let mut manifests = list(archive)?;
+    let removed = manifests.len().saturating_sub(keep);
+    for manifest in manifests.drain(..removed) {
+        std::fs::remove_file(manifest_path(archive, manifest.id))?;
+        pruned.snapshots += 1;
+    }
+
+    let referenced = manifests
+        .iter()
+        .flat_map(|manifest| manifest.files.iter().map(|file| file.blake3.as_str()))
+        .collect::<HashSet<_>>();
+    for folder in std::fs::read_dir(archive.join(BLOBS))? {
+        let folder = folder?;
+        if !folder.file_type()?.is_dir() {
+            continue;
+        }
+        for blob in std::fs::read_dir(folder.path())? {
+            let blob = blob?;
+            if !referenced.contains(blob.file_name().to_string_lossy().as_ref()) {
+                pruned.bytes += blob.metadata()?.len();
+                std::fs::remove_file(blob.path())?;
+                pruned.blobs += 1;
+            }
+        }
+    }
+
+    Ok(pruned)
+}
+
+/// Prevents other processes from creating or pruning snapshots in an archive
+/// folder until it is dropped.
+struct ArchiveLock(PathBuf);
+
+impl ArchiveLock {
+    fn acquire(archive: &Path) -> anyhow::Result<Self> {
+        let path = archive.join(LOCK);
+        match std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(_) => Ok(Self(path)),
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => anyhow::bail!(
+                "{} is in use by another process. If it isn't, remove {} and try again.",
+                archive.display(),
+                path.display()
+            ),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+impl Drop for ArchiveLock {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// The first entry of a portable archive, identifying its format.
+const HEADER_ENTRY: &str = "dossier-archive.ron";
+/// The entry of a portable archive that holds its [`Manifest`].
+const MANIFEST_ENTRY: &str = "manifest.ron";
+/// The version of the portable format written by [`create_portable`].
+const PORTABLE_VERSION: u32 = 1;
+/// The size of the chunks blobs are read in.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PortableHeader {
+    version: u32,
+}
+
+/// The pieces of an archive, in the order they are written and read.
+#[derive(Debug)]
+enum Record {
+    Manifest(Box<Manifest>),
+    /// The start of a blob, whose `len` bytes follow as [`Record::Data`].
+    Blob {
+        blake3: String,
+        len: u64,
+    },
+    Data(Vec<u8>),
+}
+
+/// Takes a snapshot of `database` as a single zstd-compressed tar file at
+/// `destination`. The file begins with a version header and the snapshot's
+/// manifest, followed by each unique blob.
+pub(crate) async fn create_portable<C: AsyncConnection + Clone + Unpin + 'static>(
+    database: &C,
+    destination: &Path,
+) -> anyhow::Result<Created> {
+    let mut files = DossierFiles::list_recursive_async("/", database)
+        .await?
+        .into_iter()
+        .filter(|file| !file.containing_path().starts_with(UPLOADS_FOLDER))
+        .collect::<Vec<_>>();
+    files.sort_by_cached_key(|file| file.path());
+
+    // The manifest is written first, so every file's hash must be known
+    // before any contents are written. The stored hashes can't be trusted,
+    // since a blob whose contents differ from its recorded hash couldn't be
+    // restored.
+    let mut archived = Vec::with_capacity(files.len());
+    let mut warnings = Vec::new();
+    for file in &mut files {
+        let metadata = *file.metadata();
+        let blake3 = compute_hash(file).await?;
+        if metadata.is_some_and(|metadata| metadata.blake3 != blake3) {
+            warnings.push(format!(
+                "{} did not match its hash; archived its current contents",
+                file.path()
+            ));
+        }
+        archived.push(ArchivedFile {
+            path: file.path(),
+            len: file.len().await?,
+            blake3: blake3::Hash::from(blake3).to_hex().to_string(),
+            written_at: metadata.map_or(0, |metadata| metadata.written_at),
+        });
+    }
+    let manifest = Manifest::new(unix_timestamp(), archived, database).await?;
+    let mut created = Created {
+        id: manifest.id,
+        files: files.len(),
+        new_blobs: 0,
+        new_bytes: 0,
+        warnings,
+    };
+
+    // The archive is written to a temporary file on a blocking thread, and
+    // only replaces `destination` once it is complete.
+    let temporary = destination.with_extension("tmp");
+    let (sender, receiver) = flume::bounded(16);
+    let writer = {
+        let temporary = temporary.clone();
+        let created_at = manifest.id;
+        tokio::task::spawn_blocking(move || write_portable(&temporary, created_at, &receiver))
+    };
+    let sent = send_portable(files, manifest, &sender, &mut created).await;
+    drop(sender);
+    // When either side fails, the other fails because the channel closed, so
+    // the error that isn't about the channel explains what went wrong.
+    let result = match (writer.await?, sent) {
+        (Ok(()), Ok(())) => Ok(()),
+        (Err(err), Ok(())) | (Ok(()), Err(err)) => Err(err),
+        (Err(written), Err(sent)) if sent.is::<flume::SendError<Record>>() => Err(written),
+        (Err(_), Err(sent)) => Err(sent),
+    };
+    if let Err(err) = result {
+        let _ = std::fs::remove_file(&temporary);
+        return Err(err);
+    }
+    std::fs::rename(&temporary, destination)?;
+
+    Ok(created)
+}
+
+async fn send_portable<C: AsyncConnection + Clone + Unpin + 'static>(
+    files: Vec<File<Async<C>, DossierFiles>>,
+    manifest: Manifest,
+    records: &flume::Sender<Record>,
+    created: &mut Created,
+) -> anyhow::Result<()> {
+    let blobs = manifest
+        .files
+        .iter()
+        .map(|file| (file.blake3.clone(), file.len))
+        .collect::<Vec<_>>();
+    records
+        .send_async(Record::Manifest(Box::new(manifest)))
+        .await?;
+
+    let mut sent = HashSet::new();
+    for (file, (blake3, len)) in files.into_iter().zip(blobs) {
+        if !sent.insert(blake3.clone()) {
+            continue;
+        }
+
+        records
+            .send_async(Record::Blob {
+                blake3: blake3.clone(),
+                len,
+            })
+            .await?;
+        let mut check = BlobCheck::new(blake3, len);
+        let mut contents = file.contents().await?;
+        while let Some(block) = contents.next().await {
+            let block = block?;
+            check.update(&block)?;
+            records.send_async(Record::Data(block)).await?;
+        }
+        check
+            .finish()
+            .map_err(|err| anyhow::anyhow!("{}: {err}", file.path()))?;
+        created.new_blobs += 1;
+        created.new_bytes += len;
+    }
+    Ok(())
+}
+
+fn write_portable(
+    destination: &Path,
+    created_at: u64,
+    records: &flume::Receiver<Record>,
+) -> anyhow::Result<()> {
+    let output = zstd::Encoder::new(std::fs::File::create(destination)?, 0)?;
+    let mut builder = tar::Builder::new(output);
+    let header = ron::to_string(&PortableHeader {
+        version: PORTABLE_VERSION,
+    })?;
+    builder.append_data(
+        &mut entry_header(header.len() as u64, created_at),
+        HEADER_ENTRY,
+        header.as_bytes(),
+    )?;
+
+    while let Ok(record) = records.recv() {
+        match record {
+            Record::Manifest(manifest) => {
+                let manifest =
+                    ron::Options::default().to_string_pretty(&manifest, PrettyConfig::default())?;
+                builder.append_data(
+                    &mut entry_header(manifest.len() as u64, created_at),
+                    MANIFEST_ENTRY,
+                    manifest.as_bytes(),
+                )?;
+            }
+            Record::Blob { blake3, len } => {
+                builder.append_data(
+                    &mut entry_header(len, created_at),
+                    format!("{BLOBS}/{blake3}"),
+                    RecordReader::new(records).take(len),
+                )?;
+            }
+            Record::Data(_) => anyhow::bail!("data received outside of a blob"),
+        }
+    }
+
+    builder.into_inner()?.finish()?.sync_all()?;
+    Ok(())
+}
+
+fn entry_header(len: u64, created_at: u64) -> tar::Header {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(len);
+    header.set_mode(0o644);
+    header.set_mtime(created_at);
+    header
+}
+
+/// Reads the [`Record::Data`] records that follow a [`Record::Blob`].
+struct RecordReader<'a> {
+    records: &'a flume::Receiver<Record>,
+    block: Vec<u8>,
+    position: usize,
+}
+
+impl<'a> RecordReader<'a> {
+    fn new(records: &'a flume::Receiver<Record>) -> Self {
+        Self {
+            records,
+            block: Vec::new(),
+            position: 0,
+        }
+    }
+}
+
+impl Read for RecordReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.position == self.block.len() {
+            match self.records.recv() {
+                Ok(Record::Data(block)) => {
+                    self.block = block;
+                    self.position = 0;
+                }
+                _ => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+            }
+        }
+
+        let read = buf.len().min(self.block.len() - self.position);
+        buf[..read].copy_from_slice(&self.block[self.position..self.position + read]);
+        self.position += read;
+        Ok(read)
+    }
+}
+
+/// Reads the archive at `source`, which is either a portable archive or an
+/// archive folder. For folders, the snapshot `snapshot` is read, defaulting to
+/// the newest.
+fn read_archive(
+    source: &Path,
+    snapshot: Option<u64>,
+    visit: impl FnMut(Record) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    if source.is_dir() {
+        read_snapshot(source, snapshot, visit)
+    } else if snapshot.is_some() {
+        anyhow::bail!("portable archives contain a single snapshot")
+    } else {
+        read_portable(source, visit)
+    }
+}
+
+fn read_portable(
+    source: &Path,
+    mut visit: impl FnMut(Record) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let mut archive = tar::Archive::new(zstd::Decoder::new(std::fs::File::open(source)?)?);
+    let mut entries = archive.entries()?;
+
+    let mut header = entries
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("the archive is empty"))??;
+    if &*header.path()? != Path::new(HEADER_ENTRY) {
+        anyhow::bail!("not a dossier archive");
+    }
+    let header = ron::de::from_reader::<_, PortableHeader>(&mut header)?;
+    if header.version != PORTABLE_VERSION {
+        anyhow::bail!("unsupported archive version {}", header.version);
+    }
+
+    let mut manifest = entries
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("the archive has no manifest"))??;
+    if &*manifest.path()? != Path::new(MANIFEST_ENTRY) {
+        anyhow::bail!("the archive has no manifest");
+    }
+    let manifest = ron::de::from_reader::<_, Manifest>(&mut manifest)?;
+    manifest.validate()?;
+    visit(Record::Manifest(Box::new(manifest)))?;
+
+    let mut buffer = vec![0; CHUNK_SIZE];
+    for entry in entries {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let blake3 = path
+            .strip_prefix(BLOBS)
+            .ok()
+            .and_then(Path::to_str)
+            .filter(|blake3| is_blob_name(blake3))
+            .ok_or_else(|| anyhow::anyhow!("unexpected entry {}", path.display()))?;
+        visit(Record::Blob {
+            blake3: blake3.to_string(),
+            len: entry.size(),
+        })?;
+        read_blob(&mut entry, &mut buffer, &mut visit)?;
+    }
+    Ok(())
+}
+
+fn read_snapshot(
+    archive: &Path,
+    snapshot: Option<u64>,
+    mut visit: impl FnMut(Record) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let manifests = list(archive)?;
+    let manifest = match snapshot {
+        Some(id) => manifests
+            .into_iter()
+            .find(|manifest| manifest.id == id)
+            .ok_or_else(|| anyhow::anyhow!("snapshot {id} not found"))?,
+        None => manifests
+            .into_iter()
+            .last()
+            .ok_or_else(|| anyhow::anyhow!("the archive has no snapshots"))?,
+    };
+    let mut seen = HashSet::new();
+    let blobs = manifest
+        .files
+        .iter()
+        .filter(|file| seen.insert(file.blake3.as_str()))
+        .map(|file| (file.blake3.clone(), file.len))
+        .collect::<Vec<_>>();
+    visit(Record::Manifest(Box::new(manifest)))?;
+
+    let mut buffer = vec![0; CHUNK_SIZE];
+    for (blake3, len) in blobs {
+        let mut blob = std::fs::File::open(blob_path(&archive.join(BLOBS), &blake3))?;
+        visit(Record::Blob { blake3, len })?;
+        read_blob(&mut blob, &mut buffer, &mut visit)?;
+    }
+    Ok(())
+}
+
+fn read_blob(
+    blob: &mut impl Read,
+    buffer: &mut [u8],
+    visit: &mut impl FnMut(Record) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    loop {
+        let read = blob.read(buffer)?;
+        if read == 0 {
+            return Ok(());
+        }
+        visit(Record::Data(buffer[..read].to_vec()))?;
+    }
+}
+
+/// Verifies that a blob's contents match its name and expected length.
+struct BlobCheck {
+    blake3: String,
+    remaining: u64,
+    hasher: blake3::Hasher,
+}
+
+impl BlobCheck {
+    fn new(blake3: String, len: u64) -> Self {
+        Self {
+            blake3,
+            remaining: len,
+            hasher: blake3::Hasher::new(),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        self.remaining = self
+            .remaining
+            .checked_sub(data.len() as u64)
+            .ok_or_else(|| anyhow::anyhow!("contents are longer than expected"))?;
+        self.hasher.update(data);
+        Ok(())
+    }
+
+    fn finish(self) -> anyhow::Result<()> {
+        if self.remaining > 0 {
+            anyhow::bail!("contents are shorter than expected");
+        } else if self.hasher.finalize().to_hex().as_str() != self.blake3 {
+            anyhow::bail!("contents do not match their hash");
+        }
+        Ok(())
+    }
+}
+
+/// The result of [`verify`].
+#[derive(Debug)]
+pub(crate) struct Verified {
+    pub id: u64,
+    pub files: usize,
+    pub blobs: usize,
+}
+
+/// Checks that every blob in the archive at `source` matches its hash, and
+/// that the snapshot's manifest doesn't refer to any missing blobs.
+pub(crate) fn verify(source: &Path, snapshot: Option<u64>) -> anyhow::Result<Verified> {
+    let mut verified = Verified {
+        id: 0,
+        files: 0,
+        blobs: 0,
+    };
+    let mut missing = HashSet::new();
+    let mut current: Option<BlobCheck> = None;
+    read_archive(source, snapshot, |record| {
+        match record {
+            Record::Manifest(manifest) => {
+                verified.id = manifest.id;
+                verified.files = manifest.files.len();
+                missing = manifest.files.into_iter().map(|file| file.blake3).collect();
+            }
+            Record::Blob { blake3, len } => {
+                if let Some(check) = current.take() {
+                    finish_check(check)?;
+                }
+                missing.remove(&blake3);
+                verified.blobs += 1;
+                current = Some(BlobCheck::new(blake3, len));
+            }
+            Record::Data(data) => {
+                if let Some(check) = &mut current {
+                    check.update(&data)?;
+                }
+            }
+        }
+        Ok(())
+    })?;
+    if let Some(check) = current {
+        finish_check(check)?;
+    }
+    if !missing.is_empty() {
+        anyhow::bail!("the archive is missing {} blobs", missing.len());
+    }
+
+    Ok(verified)
+}
+
+fn finish_check(check: BlobCheck) -> anyhow::Result<()> {
+    let blake3 = check.blake3.clone();
+    check
+        .finish()
+        .map_err(|err| anyhow::anyhow!("blob {blake3}: {err}"))
+}
+
+/// The result of [`restore`].
+#[derive(Debug, Default)]
+pub(crate) struct Restored {
+    pub projects: usize,
+    pub api_tokens: usize,
+    pub aliases: usize,
+    pub retention_rules: usize,
+    pub files: usize,
+}
+
+/// A blob being restored into the first file that contains it.
+struct Restoring<C: AsyncConnection + Clone + Unpin + 'static> {
+    file: File<Async<C>, DossierFiles>,
+    check: BlobCheck,
+    /// The files with the blob's contents, the first of which is `file`.
+    files: Vec<ArchivedFile>,
+}
+
+/// Restores the archive at `source` into `database`, which must not contain
+/// any projects or files. For archive folders, the snapshot `snapshot` is
+/// restored, defaulting to the newest.
+///
+/// API tokens are restored without their authentication tokens, which are
+/// stored in the server's admin database.
+///
+/// If restoring fails partway, the records and files restored so far are
+/// removed again, so the restore can be retried once the problem is fixed.
+pub(crate) async fn restore<C: AsyncConnection + Clone + Unpin + 'static>(
+    database: &C,
+    source: &Path,
+    snapshot: Option<u64>,
+) -> anyhow::Result<Restored> {
+    if !Project::all_async(database).await?.is_empty()
+        || !DossierFiles::list_recursive_async("/", database)
+            .await?
+            .is_empty()
+    {
+        anyhow::bail!("archives can only be restored into an empty database");
+    }
+
+    let mut inserted = Inserted::default();
+    match restore_records(database, source, snapshot, &mut inserted).await {
+        Ok(restored) => Ok(restored),
+        Err(err) => match inserted.remove(database).await {
+            Ok(()) => Err(err.context("restoring failed, and the restored records were removed")),
+            Err(cleanup) => Err(err.context(format!(
+                "restoring failed, and removing the restored records failed too ({cleanup:#}). \
+                 Restore into a new, empty database instead"
+            ))),
+        },
+    }
+}
+
+/// The ids of the records inserted by [`restore`], so that a failed restore
+/// can be undone.
+#[derive(Default)]
+struct Inserted {
+    projects: Vec<u32>,
+    api_tokens: Vec<u64>,
+    aliases: Vec<u64>,
+    retention_rules: Vec<u64>,
+}
+
+impl Inserted {
+    /// Deletes the inserted records and every file. [`restore`] only runs on
+    /// a database without files, so all of them were restored.
+    async fn remove<C: AsyncConnection + Clone + Unpin + 'static>(
+        &self,
+        database: &C,
+    ) -> anyhow::Result<()> {
+        for file in DossierFiles::list_recursive_async("/", database).await? {
+            DossierFiles::delete_async(&file.path(), database).await?;
+        }
+        for id in &self.retention_rules {
+            if let Some(rule) = RetentionRule::get_async(id, database).await? {
+                rule.delete_async(database).await?;
+            }
+        }
+        for id in &self.aliases {
+            if let Some(alias) = Alias::get_async(id, database).await? {
+                alias.delete_async(database).await?;
+            }
+        }
+        for id in &self.api_tokens {
+            if let Some(api_token) = ApiToken::get_async(id, database).await? {
+                api_token.delete_async(database).await?;
+            }
+        }
+        for id in &self.projects {
+            if let Some(project) = Project::get_async(id, database).await? {
+                project.delete_async(database).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn restore_records<C: AsyncConnection + Clone + Unpin + 'static>(
+    database: &C,
+    source: &Path,
+    snapshot: Option<u64>,
+    inserted: &mut Inserted,
+) -> anyhow::Result<Restored> {
+    let (sender, receiver) = flume::bounded(16);
+    let reader = {
+        let source = source.to_path_buf();
+        tokio::task::spawn_blocking(move || {
+            read_archive(&source, snapshot, |record| Ok(sender.send(record)?))
+        })
+    };
+
+    let mut restored = Restored::default();
+    let mut pending = HashMap::<String, Vec<ArchivedFile>>::new();
+    let mut current: Option<Restoring<C>> = None;
+    while let Ok(record) = receiver.recv_async().await {
+        match record {
+            Record::Manifest(manifest) => {
+                for (id, project) in manifest.projects {
+                    project
+                        .insert_into_async(&id, database)
+                        .await
+                        .map_err(|err| err.error)?;
+                    inserted.projects.push(id);
+                    restored.projects += 1;
+                }
+                for (id, api_token) in manifest.api_tokens {
+                    api_token
+                        .insert_into_async(&id, database)
+                        .await
+                        .map_err(|err| err.error)?;
+                    inserted.api_tokens.push(id);
+                    restored.api_tokens += 1;
+                }
+                for (id, alias) in manifest.aliases {
+                    alias
+                        .insert_into_async(&id, database)
+                        .await
+                        .map_err(|err| err.error)?;
+                    inserted.aliases.push(id);
+                    restored.aliases += 1;
+                }
+                for (id, rule) in manifest.retention_rules {
+                    rule.insert_into_async(&id, database)
+                        .await
+                        .map_err(|err| err.error)?;
+                    inserted.retention_rules.push(id);
+                    restored.retention_rules += 1;
+                }
+                for file in manifest.files {
+                    pending.entry(file.blake3.clone()).or_default().push(file);
+                }
+            }
+            Record::Blob { blake3, len } => {
+                if let Some(restoring) = current.take() {
+                    restored.files += finish_restoring(restoring, database).await?;
+                }
+                // Blobs that no file refers to are skipped.
+                if let Some(files) = pending.remove(&blake3) {
+                    let file = DossierFiles::build(&files[0].path)
+                        .create_async(database)
+                        .await?;
+                    current = Some(Restoring {
+                        file,
+                        check: BlobCheck::new(blake3, len),
+                        files,
+                    });
+                }
+            }
+            Record::Data(data) => {
+                if let Some(restoring) = &mut current {
+                    restoring.check.update(&data)?;
+                    restoring.file.append(&data).await?;
+                }
+            }
+        }
+    }
+    // An error reading the archive explains why the records ended early.
+    reader.await??;
+    if let Some(restoring) = current {
+        restored.files += finish_restoring(restoring, database).await?;
+    }
+    if !pending.is_empty() {
+        anyhow::bail!(
+            "the archive is missing the contents of {} files",
+            pending.values().map(Vec::len).sum::<usize>()
+        );
+    }
+
+    Ok(restored)
+}
+
+/// Verifies a restored blob and writes each file's metadata, copying the blob
+/// to every other file with the same contents. Returns the number of files
+/// restored.
+async fn finish_restoring<C: AsyncConnection + Clone + Unpin + 'static>(
+    restoring: Restoring<C>,
+    database: &C,
+) -> anyhow::Result<usize> {
+    let Restoring {
+        mut file,
+        check,
+        files,
+    } = restoring;
+    let blake3 = *blake3::Hash::from_hex(&check.blake3)?.as_bytes();
+    check
+        .finish()
+        .map_err(|err| anyhow::anyhow!("{}: {err}", files[0].path))?;
+
+    for (index, archived) in files.iter().enumerate() {
+        let metadata = Some(Metadata {
+            blake3,
+            written_at: archived.written_at,
+        });
+        if index == 0 {
+            *file.metadata_mut() = metadata;
+            file.update_metadata().await?;
+            continue;
+        }
+
+        let mut copy = DossierFiles::build(&archived.path)
+            .create_async(database)
+            .await?;
+        let mut contents = file.contents().await?;
+        while let Some(block) = contents.next().await {
+            copy.append(&block?).await?;
+        }
+        *copy.metadata_mut() = metadata;
+        copy.update_metadata().await?;
+    }
+    Ok(files.len())
+}
+
+fn manifest_path(archive: &Path, id: u64) -> PathBuf {
+    archive.join(SNAPSHOTS).join(format!("{id}.ron"))
+}
+
+/// Returns true if `name` is a lowercase hex-encoded blake3 hash, which is how
+/// blobs are named.
+fn is_blob_name(name: &str) -> bool {
+    name.len() == 64
+        && name
+            .bytes()
+            .all(|byte| matches!(byte, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// Returns the path of the blob named `blake3`, which must be a valid blob
+/// name. Blobs are grouped into folders by the first two characters of their
+/// hash to keep folders small.
+fn blob_path(blobs: &Path, blake3: &str) -> PathBuf {
+    blobs.join(&blake3[..2]).join(blake3)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::RetentionPolicy;
+
+    fn blob(contents: &str) -> String {
+        blake3::hash(contents.as_bytes()).to_hex().to_string()
+    }
+
+    fn write_snapshot(archive: &Path, id: u64, contents: &[&str]) {
+        for contents in contents {
+            let path = blob_path(&archive.join(BLOBS), &blob(contents));
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+        let manifest = Manifest {
+            id,
+            projects: Vec::new(),
+            api_tokens: Vec::new(),
+            aliases: Vec::new(),
+            retention_rules: Vec::new(),
+            files: contents
+                .iter()
+                .map(|contents| ArchivedFile {
+                    path: format!("/project/{contents}"),
+                    len: contents.len() as u64,
+                    blake3: blob(contents),
+                    written_at: id,
+                })
+                .collect(),
+        };
+        std::fs::write(
+            manifest_path(archive, id),
+            ron::to_string(&manifest).unwrap(),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn portable() {
+        let path = std::env::temp_dir().join(format!(
+            "dossier-portable-test-{:016x}.tar.zst",
+            rand::random::<u64>()
+        ));
+        let file = |path: &str, contents: &str| ArchivedFile {
+            path: path.to_string(),
+            len: contents.len() as u64,
+            blake3: blob(contents),
+            written_at: 1,
+        };
+        let write = |blobs: &[(&str, u64)]| {
+            let (sender, receiver) = flume::unbounded();
+            sender
+                .send(Record::Manifest(Box::new(Manifest {
+                    id: 1,
+                    projects: Vec::new(),
+                    api_tokens: Vec::new(),
+                    aliases: Vec::new(),
+                    retention_rules: Vec::new(),
+                    files: vec![
+                        file("/a/1", "one"),
+                        file("/a/2", "two"),
+                        file("/b/1", "one"),
+                    ],
+                })))
+                .unwrap();
+            for (contents, len) in blobs {
+                sender
+                    .send(Record::Blob {
+                        blake3: blob(contents),
+                        len: *len,
+                    })
+                    .unwrap();
+                sender
+                    .send(Record::Data(contents.as_bytes().to_vec()))
+                    .unwrap();
+            }
+            drop(sender);
+            write_portable(&path, 1, &receiver)
+        };
+
+        write(&[("one", 3), ("two", 3)]).unwrap();
+        let verified = verify(&path, None).unwrap();
+        assert_eq!(verified.files, 3);
+        assert_eq!(verified.blobs, 2);
+
+        // A blob that is missing, or whose data ends early, is detected.
+        write(&[("one", 3)]).unwrap();
+        assert!(verify(&path, None).is_err());
+        assert!(write(&[("one", 3), ("two", 5)]).is_err());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn portable_manifest() {
+        let path = std::env::temp_dir().join(format!(
+            "dossier-portable-test-{:016x}.tar.zst",
+            rand::random::<u64>()
+        ));
+        let (sender, receiver) = flume::unbounded();
+        sender
+            .send(Record::Manifest(Box::new(Manifest {
+                id: 1,
+                projects: Vec::new(),
+                api_tokens: Vec::new(),
+                aliases: vec![(
+                    2,
+                    Alias {
+                        path: "/docs/latest/".to_string(),
+                        target: "/docs/v1/".to_string(),
+                        redirect: true,
+                    },
+                )],
+                retention_rules: vec![(
+                    3,
+                    RetentionRule {
+                        project_id: 4,
+                        pattern: "/pr-*/".to_string(),
+                        policy: RetentionPolicy::KeepNewest(5),
+                    },
+                )],
+                files: Vec::new(),
+            })))
+            .unwrap();
+        drop(sender);
+        write_portable(&path, 1, &receiver).unwrap();
+
+        let mut manifests = Vec::new();
+        read_archive(&path, None, |record| {
+            if let Record::Manifest(manifest) = record {
+                manifests.push(manifest);
+            }
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(manifests.len(), 1);
+        let manifest = &manifests[0];
+        assert_eq!(manifest.aliases.len(), 1);
+        let (id, alias) = &manifest.aliases[0];
+        assert_eq!(*id, 2);
+        assert_eq!(alias.path, "/docs/latest/");
+        assert_eq!(alias.target, "/docs/v1/");
+        assert!(alias.redirect);
+        assert_eq!(manifest.retention_rules.len(), 1);
+        let (id, rule) = &manifest.retention_rules[0];
+        assert_eq!(*id, 3);
+        assert_eq!(rule.project_id, 4);
+        assert_eq!(rule.pattern, "/pr-*/");
+        assert!(matches!(rule.policy, RetentionPolicy::KeepNewest(5)));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn pruning() {
+        let archive = std::env::temp_dir().join(format!(
+            "dossier-archive-test-{:016x}",
+            rand::random::<u64>()
+        ));
+        std::fs::create_dir_all(archive.join(SNAPSHOTS)).unwrap();
+        write_snapshot(&archive, 1, &["a", "b"]);
+        write_snapshot(&archive, 2, &["b", "c"]);
+        write_snapshot(&archive, 3, &["c", "d"]);
+
+        let ids = |archive: &Path| {
+            list(archive)
+                .unwrap()
+                .into_iter()
+                .map(|manifest| manifest.id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids(&archive), [1, 2, 3]);
+
+        let pruned = prune(&archive, 2).unwrap();
+        assert_eq!(pruned.snapshots, 1);
+        assert_eq!(pruned.blobs, 1);
+        assert_eq!(ids(&archive), [2, 3]);
+        assert!(!blob_path(&archive.join(BLOBS), &blob("a")).exists());
+        assert!(blob_path(&archive.join(BLOBS), &blob("b")).exists());
+
+        // Every snapshot can't be removed, and pruning waits for snapshots
+        // being created.
+        assert!(prune(&archive, 0).is_err());
+        let lock = ArchiveLock::acquire(&archive).unwrap();
+        assert!(prune(&archive, 1).is_err());
+        drop(lock);
+
+        let pruned = prune(&archive, 1).unwrap();
+        assert_eq!(pruned.snapshots, 1);
+        assert_eq!(pruned.blobs, 1);
+        assert_eq!(ids(&archive), [3]);
+
+        // Manifests that don't name their blobs by hash are rejected.
+        let mut manifest = list(&archive).unwrap().pop().unwrap();
+        manifest.files[0].blake3 = "a".to_string();
+        std::fs::write(
+            manifest_path(&archive, 3),
+            ron::to_string(&manifest).unwrap(),
+        )
+        .unwrap();
+        assert!(list(&archive).is_err());
+
+        std::fs::remove_dir_all(archive).unwrap();
+    }
+}
@@ -1,11 +1,13 @@
 use std::{
     cmp::Ordering,
-    collections::{HashMap, HashSet},
-    path::{Path, PathBuf},
-    sync::{
-        atomic::{self, AtomicU8},
-        Arc,
-    },
+    collections::{BTreeSet, HashMap, HashSet},
+    fmt::Display,
+    io::Write,
+    num::NonZeroUsize,
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
 use bonsaidb::{
@@ -20,23 +22,38 @@ use bonsaidb::{
         },
         schema::{NamedReference, SerializedCollection},
     },
-    files::{
-        direct::{Async, File},
-        FileConfig,
-    },
+    files::FileConfig,
     local::config::Builder,
     server::{CustomServer, ServerConfiguration},
     AnyDatabase, AnyServerConnection,
 };
-use clap::Subcommand;
+use clap::{ArgGroup, Args, Subcommand, ValueEnum};
+use notify_debouncer_mini::{new_debouncer, notify::RecursiveMode};
 use parking_lot::Mutex;
-use ron::ser::PrettyConfig;
+use rand::Rng;
+use serde::Serialize;
 use tokio::{fs, io::AsyncReadExt};
 
 use crate::{
-    api::{self, DeleteFile, DossierApiHandler, ListFiles, WriteFileData},
-    compactor, permissions,
-    schema::{ApiToken, Dossier, DossierFiles, Project},
+    api::{
+        self, unix_timestamp, CopyFiles, DeleteAlias, DeleteFile, DeleteFiles, DossierApiHandler,
+        FinishUpload, GetServerStatus, GetUploadStatus, GetUsage, ListAliases, ListFiles,
+        ListRemoteFiles, MoveFiles, ReadFile, RemoteFile, SetAlias, StartUpload, StatFile,
+        UploadChunk, UploadStatus, WriteFileData, MAX_READ_LEN, UPLOADS_FOLDER,
+    },
+    archive, compactor, fsck, gc,
+    metrics::{self, metrics},
+    permissions,
+    progress::{format_bytes, format_duration, Progress, SyncSummary},
+    retention,
+    schema::{
+        Alias, ApiToken, Dossier, DossierFiles, Project, ProjectQuota, RetentionPolicy,
+        RetentionRule,
+    },
+    status::{self, ServerStatus},
+    sync_filter::{SyncFilter, IGNORE_FILE_NAME},
+    uploads,
+    usage::FolderUsage,
     webserver, CliBackend,
 };
 
@@ -47,8 +64,97 @@ pub(crate) enum Cli {
     #[clap(subcommand)]
     ApiToken(ApiTokenCommand),
     Compact,
-    Archive {
+    /// Prints the server's version, uptime, and storage, and when the
+    /// database was last compacted.
+    Status,
+    /// Removes abandoned uploads, which also happens before each scheduled
+    /// compaction, and blocks of file contents whose file no longer exists.
+    Gc {
+        /// Uploads that haven't received data in this many hours are removed.
+        #[clap(long, default_value_t = 24)]
+        max_idle_hours: u64,
+        /// Print what would be removed without removing it.
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Verifies every file against its stored hash and looks for files that
+    /// can't be reached.
+    Fsck {
+        /// Compute missing hashes and delete orphaned uploads and blocks.
+        #[clap(long)]
+        repair: bool,
+    },
+    /// Manages archives of the database. Each archive holds any number of
+    /// snapshots, and file contents are only stored once across all of them.
+    Archive(ArchiveArgs),
+}
+
+#[derive(Debug, Args)]
+#[clap(args_conflicts_with_subcommands = true, arg_required_else_help = true)]
+pub(crate) struct ArchiveArgs {
+    #[clap(subcommand)]
+    command: Option<ArchiveCommand>,
+    /// Deprecated: use `archive create` instead.
+    #[clap(hide = true)]
+    destination: Option<PathBuf>,
+}
+
+impl ArchiveArgs {
+    /// Returns the command to run, treating the deprecated `archive
+    /// <destination>` as `archive create <destination>`.
+    fn into_command(self) -> anyhow::Result<ArchiveCommand> {
+        match (self.command, self.destination) {
+            (Some(command), _) => Ok(command),
+            (None, Some(destination)) => {
+                eprintln!(
+                    "warning: `archive <destination>` is deprecated, use `archive create \
+                     <destination>` instead. The destination now holds snapshots rather than a \
+                     copy of each file."
+                );
+                Ok(ArchiveCommand::Create {
+                    destination,
+                    format: ArchiveFormat::Directory,
+                })
+            }
+            (None, None) => anyhow::bail!("an archive command is required"),
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub(crate) enum ArchiveCommand {
+    /// Takes a snapshot, storing only file contents not already archived.
+    Create {
         destination: PathBuf,
+        /// Write a single file containing one snapshot instead of adding a
+        /// snapshot to an archive folder.
+        #[clap(long, value_enum, default_value_t = ArchiveFormat::Directory)]
+        format: ArchiveFormat,
+    },
+    /// Restores a snapshot into an empty database.
+    Restore {
+        archive: PathBuf,
+        /// The snapshot to restore from an archive folder. Defaults to the
+        /// newest.
+        #[clap(long)]
+        snapshot: Option<u64>,
+    },
+    /// Checks that a snapshot's contents match their hashes.
+    Verify {
+        archive: PathBuf,
+        /// The snapshot to verify in an archive folder. Defaults to the
+        /// newest.
+        #[clap(long)]
+        snapshot: Option<u64>,
+    },
+    /// Lists the snapshots in an archive.
+    List { archive: PathBuf },
+    /// Removes old snapshots and the file contents only they refer to.
+    Prune {
+        archive: PathBuf,
+        /// The number of snapshots to keep.
+        #[clap(long)]
+        keep: usize,
     },
 }
 
@@ -67,7 +173,271 @@ pub(crate) enum ProjectCommand {
         project: String,
         location: PathBuf,
         remote_path: String,
+        #[clap(flatten)]
+        options: SyncOptions,
+    },
+    /// Lists the files in a remote folder.
+    Ls {
+        project: String,
+        #[clap(default_value = "/")]
+        path: String,
+        /// List the files in all subfolders.
+        #[clap(long, short = 'r')]
+        recursive: bool,
+    },
+    /// Writes the contents of a remote file to stdout.
+    Cat {
+        project: String,
+        path: String,
+    },
+    /// Downloads a remote file or folder.
+    Get {
+        project: String,
+        remote_path: String,
+        location: PathBuf,
+    },
+    /// Prints information about a remote file.
+    Stat {
+        project: String,
+        path: String,
     },
+    /// Copies a remote file or folder on the server. Folders must end with a
+    /// `/`.
+    Cp {
+        project: String,
+        source: String,
+        destination: String,
+        #[clap(flatten)]
+        options: TransferOptions,
+    },
+    /// Moves a remote file or folder on the server. Folders must end with a
+    /// `/`.
+    Mv {
+        project: String,
+        source: String,
+        destination: String,
+        #[clap(flatten)]
+        options: TransferOptions,
+    },
+    #[clap(subcommand)]
+    Alias(AliasCommand),
+    #[clap(subcommand)]
+    Retention(RetentionCommand),
+    /// Prints the storage used by each folder in a project, or in every project
+    /// when no project is given.
+    Usage {
+        project: Option<String>,
+        /// How many levels of folders to report below each project.
+        #[clap(long, default_value_t = 1)]
+        depth: usize,
+    },
+    /// Prints a project's storage usage and quota, updating any limits
+    /// provided.
+    Quota {
+        project: String,
+        /// The total size of the project's files, in bytes.
+        #[clap(long)]
+        max_bytes: Option<u64>,
+        /// The number of files in the project.
+        #[clap(long)]
+        max_files: Option<usize>,
+        /// The size of any single file, in bytes.
+        #[clap(long)]
+        max_file_size: Option<u64>,
+        /// Remove every limit before applying the limits provided.
+        #[clap(long)]
+        unlimited: bool,
+    },
+}
+
+/// Manages rules that remove stale folders, such as pull request previews.
+#[derive(Debug, Subcommand)]
+pub(crate) enum RetentionCommand {
+    /// Adds a rule for the folders matching `pattern`, e.g. `/pr-*/`.
+    #[clap(group(ArgGroup::new("policy").required(true).args(["max_age_days", "keep_newest"])))]
+    Add {
+        project: String,
+        pattern: String,
+        /// Remove folders that haven't been written to in this many days.
+        #[clap(long)]
+        max_age_days: Option<u32>,
+        /// Keep only this many of the most recently written folders.
+        #[clap(long)]
+        keep_newest: Option<usize>,
+    },
+    /// Lists a project's rules.
+    List { project: String },
+    /// Removes a rule.
+    Remove { id: u64 },
+    /// Applies every rule now. Rules are also applied hourly by the server.
+    Run {
+        /// Print the folders that would be removed without removing them.
+        #[clap(long)]
+        dry_run: bool,
+    },
+}
+
+/// Manages folders that serve the contents of other folders.
+#[derive(Debug, Subcommand)]
+pub(crate) enum AliasCommand {
+    /// Creates or repoints an alias, e.g. `/latest/` to `/v0.5/`.
+    Set {
+        project: String,
+        path: String,
+        target: String,
+        /// Redirect visitors to the target instead of serving its files from
+        /// the alias's path.
+        #[clap(long)]
+        redirect: bool,
+    },
+    /// Removes an alias.
+    Delete { project: String, path: String },
+    /// Lists a project's aliases.
+    List { project: String },
+}
+
+#[derive(Debug, Args)]
+pub(crate) struct TransferOptions {
+    /// The project to copy or move into. Defaults to the source project.
+    #[clap(long)]
+    to_project: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub(crate) struct SyncOptions {
+    /// Skip files matching this gitignore-style pattern. May be repeated.
+    ///
+    /// Patterns from a `.dossierignore` file in the root of the synchronized
+    /// directory are also excluded.
+    #[clap(long)]
+    exclude: Vec<String>,
+    /// Only sync files matching this gitignore-style pattern. May be repeated.
+    #[clap(long)]
+    include: Vec<String>,
+    /// Print the operations that would be performed without performing them.
+    #[clap(long)]
+    dry_run: bool,
+    /// Never delete remote files that are missing locally.
+    #[clap(long)]
+    no_delete: bool,
+    /// Abort without changing anything if more than this many remote files
+    /// would be deleted. Accepts a count, e.g. `100`, or a percentage of the
+    /// remote files being synced, e.g. `25%`.
+    #[clap(long)]
+    max_delete: Option<DeleteLimit>,
+    /// Allow the sync to delete every remote file. Without this flag, the
+    /// server refuses to empty a folder that contains files.
+    #[clap(long)]
+    force: bool,
+    /// The format to print the sync plan in. When `json` is used, the plan is
+    /// always printed to stdout, and progress is reported on stderr.
+    #[clap(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+    /// The number of files to hash and upload concurrently. Defaults to a
+    /// multiple of the number of available CPUs.
+    #[clap(long, short = 'j')]
+    jobs: Option<NonZeroUsize>,
+    /// The number of times a failed upload is retried before it is reported
+    /// as failed. Retries are delayed using exponential backoff.
+    #[clap(long, default_value_t = 5)]
+    retries: u32,
+    /// Only print errors and the summary once the sync completes.
+    #[clap(long, short = 'q')]
+    quiet: bool,
+    /// After syncing, keep running and sync files as they change.
+    #[clap(long, conflicts_with = "dry_run")]
+    watch: bool,
+}
+
+impl SyncOptions {
+    fn status(&self, message: impl Display) {
+        if !self.quiet {
+            self.format.status(message);
+        }
+    }
+
+    fn hash_tasks(&self) -> usize {
+        self.jobs
+            .map_or_else(|| available_parallelism() * 2, NonZeroUsize::get)
+    }
+
+    fn upload_tasks(&self) -> usize {
+        self.jobs
+            .map_or_else(|| available_parallelism() * 4, NonZeroUsize::get)
+    }
+}
+
+fn available_parallelism() -> usize {
+    std::thread::available_parallelism().map_or(8, NonZeroUsize::get)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum DeleteLimit {
+    Count(usize),
+    Percent(f64),
+}
+
+impl DeleteLimit {
+    fn allows(self, deletes: usize, remote_files: usize) -> bool {
+        match self {
+            DeleteLimit::Count(count) => deletes <= count,
+            DeleteLimit::Percent(percent) => {
+                remote_files == 0 || deletes as f64 / remote_files as f64 * 100. <= percent
+            }
+        }
+    }
+}
+
+impl FromStr for DeleteLimit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(percent) = s.strip_suffix('%') {
+            match percent.parse::<f64>() {
+                Ok(percent) if (0. ..=100.).contains(&percent) => Ok(Self::Percent(percent)),
+                _ => Err(format!("invalid percentage: {s}")),
+            }
+        } else {
+            s.parse()
+                .map(Self::Count)
+                .map_err(|_| format!("expected a count or a percentage: {s}"))
+        }
+    }
+}
+
+impl Display for DeleteLimit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeleteLimit::Count(count) => write!(f, "{count}"),
+            DeleteLimit::Percent(percent) => write!(f, "{percent}%"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, ValueEnum)]
+pub(crate) enum ArchiveFormat {
+    /// A folder of snapshots that share their file contents.
+    Directory,
+    /// A single zstd-compressed tar file.
+    #[value(name = "tar.zst")]
+    TarZst,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, ValueEnum)]
+pub(crate) enum OutputFormat {
+    Text,
+    Json,
+}
+
+impl OutputFormat {
+    /// Prints a progress message. Progress is written to stderr when the
+    /// output is machine-readable so that stdout remains parseable.
+    pub(crate) fn status(self, message: impl Display) {
+        match self {
+            OutputFormat::Text => println!("{message}"),
+            OutputFormat::Json => eprintln!("{message}"),
+        }
+    }
 }
 
 #[derive(Debug, Subcommand)]
@@ -83,7 +453,7 @@ impl CommandLine for CliBackend {
     type Subcommand = Cli;
 
     async fn configuration(&mut self) -> anyhow::Result<ServerConfiguration<CliBackend>> {
-        Ok(ServerConfiguration::new("dossier.bonsaidb")
+        Ok(ServerConfiguration::new(status::STORAGE_PATH)
             .default_permissions(
                 Statement::for_any()
                     .allowing(&BonsaiAction::Server(ServerAction::Connect))
@@ -97,19 +467,42 @@ impl CommandLine for CliBackend {
             .with_schema::<Dossier>()?
             .with_api::<DossierApiHandler, ListFiles>()?
             .with_api::<DossierApiHandler, WriteFileData>()?
-            .with_api::<DossierApiHandler, DeleteFile>()?)
+            .with_api::<DossierApiHandler, DeleteFile>()?
+            .with_api::<DossierApiHandler, DeleteFiles>()?
+            .with_api::<DossierApiHandler, StartUpload>()?
+            .with_api::<DossierApiHandler, UploadChunk>()?
+            .with_api::<DossierApiHandler, GetUploadStatus>()?
+            .with_api::<DossierApiHandler, FinishUpload>()?
+            .with_api::<DossierApiHandler, CopyFiles>()?
+            .with_api::<DossierApiHandler, MoveFiles>()?
+            .with_api::<DossierApiHandler, SetAlias>()?
+            .with_api::<DossierApiHandler, DeleteAlias>()?
+            .with_api::<DossierApiHandler, ListAliases>()?
+            .with_api::<DossierApiHandler, GetUsage>()?
+            .with_api::<DossierApiHandler, GetServerStatus>()?
+            .with_api::<DossierApiHandler, ListRemoteFiles>()?
+            .with_api::<DossierApiHandler, StatFile>()?
+            .with_api::<DossierApiHandler, ReadFile>()?)
     }
 
     async fn open_server(&mut self) -> anyhow::Result<CustomServer<Self::Backend>> {
+        status::mark_started();
         let server = CustomServer::<Self::Backend>::open(self.configuration().await?).await?;
 
         let dossier = server.create_database::<Dossier>("dossier", true).await?;
 
         permissions::initialize(&server).await?;
 
-        webserver::launch(server.clone(), dossier.clone());
+        metrics().refresh_projects(&dossier).await?;
+        metrics::launch(dossier.clone());
+
+        webserver::launch(server.clone(), dossier.clone())?;
 
-        compactor::launch(dossier);
+        uploads::launch(dossier.clone());
+
+        retention::launch(dossier.clone());
+
+        compactor::launch(dossier)?;
 
         Ok(server)
     }
@@ -122,7 +515,16 @@ impl CommandLine for CliBackend {
         let database = connection.database::<Dossier>("dossier").await?;
         match command {
             Cli::Project(ProjectCommand::Create { slug }) => {
-                let new_project = Project { slug }.push_into_async(&database).await?;
+                if slug.starts_with('_') {
+                    // Paths beginning with `/_` are routed to the server.
+                    anyhow::bail!("project slugs must not start with '_'");
+                }
+                let new_project = Project {
+                    slug,
+                    quota: ProjectQuota::default(),
+                }
+                .push_into_async(&database)
+                .await?;
                 println!("Project #{} created.", new_project.header.id);
             }
             Cli::Project(ProjectCommand::List) => {
@@ -136,12 +538,246 @@ impl CommandLine for CliBackend {
                 location,
                 remote_path,
                 project,
-            }) => upload_file(&location, &remote_path, &project, &database, None).await?,
+            }) => {
+                let remote_path =
+                    upload_file(&location, &remote_path, &project, &database, None).await?;
+                println!("File uploaded to {remote_path}");
+            }
             Cli::Project(ProjectCommand::Sync {
                 location,
                 remote_path,
                 project,
-            }) => sync_directory(location, remote_path, &project, &database).await?,
+                options,
+            }) => {
+                if options.watch {
+                    watch_directory(location, remote_path, &project, &options, &database).await?;
+                } else {
+                    sync_directory(location, remote_path, &project, &options, &database).await?;
+                }
+            }
+            Cli::Project(ProjectCommand::Ls {
+                project,
+                path,
+                recursive,
+            }) => list_remote_folder(&project, &path, recursive, &database).await?,
+            Cli::Project(ProjectCommand::Cat { project, path }) => {
+                let path = project_path(&project, &path);
+                let mut stdout = std::io::stdout().lock();
+                read_remote_file(&path, &database, |data| Ok(stdout.write_all(data)?)).await?;
+                stdout.flush()?;
+            }
+            Cli::Project(ProjectCommand::Get {
+                project,
+                remote_path,
+                location,
+            }) => download(&project, &remote_path, &location, &database).await?,
+            Cli::Project(ProjectCommand::Stat { project, path }) => {
+                let path = project_path(&project, &path);
+                let file = stat_file(&path, &database)
+                    .await?
+                    .ok_or_else(|| anyhow::anyhow!("{path} not found"))?;
+                println!("path:   {}", file.path);
+                println!("id:     {}", file.id);
+                println!("size:   {}", file.len);
+                match file.blake3 {
+                    Some(blake3) => println!("blake3: {}", blake3::Hash::from(blake3).to_hex()),
+                    None => println!("blake3: none, the file is incomplete"),
+                }
+            }
+            Cli::Project(ProjectCommand::Cp {
+                project,
+                source,
+                destination,
+                options,
+            }) => {
+                let source = project_path(&project, &source);
+                let destination = project_path(
+                    options.to_project.as_ref().unwrap_or(&project),
+                    &destination,
+                );
+                let copied = copy_files(source, destination, &database).await?;
+                println!("Copied {copied} files");
+            }
+            Cli::Project(ProjectCommand::Mv {
+                project,
+                source,
+                destination,
+                options,
+            }) => {
+                let source = project_path(&project, &source);
+                let destination = project_path(
+                    options.to_project.as_ref().unwrap_or(&project),
+                    &destination,
+                );
+                let moved = move_files(source, destination, &database).await?;
+                println!("Moved {moved} files");
+            }
+            Cli::Project(ProjectCommand::Alias(AliasCommand::Set {
+                project,
+                path,
+                target,
+                redirect,
+            })) => {
+                let path = normalize_remote_folder(project_path(&project, &path));
+                let target = normalize_remote_folder(project_path(&project, &target));
+                set_alias(&path, &target, redirect, &database).await?;
+                println!("{path} now serves {target}");
+            }
+            Cli::Project(ProjectCommand::Alias(AliasCommand::Delete { project, path })) => {
+                let path = normalize_remote_folder(project_path(&project, &path));
+                if delete_alias(&path, &database).await? {
+                    println!("Alias {path} deleted");
+                } else {
+                    anyhow::bail!("alias {path} not found");
+                }
+            }
+            Cli::Project(ProjectCommand::Alias(AliasCommand::List { project })) => {
+                for alias in list_aliases(&format!("/{project}/"), &database).await? {
+                    let mode = if alias.redirect {
+                        "redirect"
+                    } else {
+                        "rewrite"
+                    };
+                    println!("{} -> {} ({mode})", alias.path, alias.target);
+                }
+            }
+            Cli::Project(ProjectCommand::Usage { project, depth }) => {
+                let projects = match project {
+                    Some(project) => vec![project],
+                    None => {
+                        let mut projects = Project::all_async(&database)
+                            .await?
+                            .into_iter()
+                            .map(|project| project.contents.slug)
+                            .collect::<Vec<_>>();
+                        projects.sort();
+                        projects
+                    }
+                };
+                println!("{:>10} {:>10} {:>8}  PATH", "SIZE", "UNIQUE", "FILES");
+                for project in projects {
+                    for folder in folder_usage(&format!("/{project}/"), depth, &database).await? {
+                        println!(
+                            "{:>10} {:>10} {:>8}  {}",
+                            format_bytes(folder.bytes),
+                            format_bytes(folder.unique_bytes),
+                            folder.files,
+                            folder.path
+                        );
+                    }
+                }
+            }
+            Cli::Project(ProjectCommand::Quota {
+                project,
+                max_bytes,
+                max_files,
+                max_file_size,
+                unlimited,
+            }) => {
+                let mut project = Project::load_async(project.as_str(), &database)
+                    .await?
+                    .ok_or_else(|| anyhow::anyhow!("project {} not found", project))?;
+                let quota = &mut project.contents.quota;
+                let original = *quota;
+                if unlimited {
+                    *quota = ProjectQuota::default();
+                }
+                quota.max_bytes = max_bytes.or(quota.max_bytes);
+                quota.max_files = max_files.or(quota.max_files);
+                quota.max_file_size = max_file_size.or(quota.max_file_size);
+                let quota = *quota;
+                if quota != original {
+                    project.update_async(&database).await?;
+                }
+
+                let usage = DossierFiles::stats_for_path_async(
+                    &format!("/{}/", project.contents.slug),
+                    &database,
+                )
+                .await?;
+                let limit = |limit: Option<String>| {
+                    limit.map_or_else(|| String::from("(no limit)"), |limit| format!("of {limit}"))
+                };
+                println!(
+                    "Storage: {} {}",
+                    format_bytes(usage.total_bytes),
+                    limit(quota.max_bytes.map(format_bytes))
+                );
+                println!(
+                    "Files: {} {}",
+                    usage.file_count,
+                    limit(quota.max_files.map(|max| max.to_string()))
+                );
+                println!(
+                    "Max file size: {}",
+                    quota
+                        .max_file_size
+                        .map_or_else(|| String::from("no limit"), format_bytes)
+                );
+            }
+            Cli::Project(ProjectCommand::Retention(RetentionCommand::Add {
+                project,
+                pattern,
+                max_age_days,
+                keep_newest,
+            })) => {
+                if !pattern.starts_with('/') || !pattern.ends_with('/') {
+                    anyhow::bail!("patterns must begin and end with '/'");
+                }
+                let project_id = NamedReference::from(&project)
+                    .id_async::<Project, _>(&database)
+                    .await?
+                    .ok_or_else(|| anyhow::anyhow!("project {} not found", project))?;
+                let policy = match (max_age_days, keep_newest) {
+                    (Some(days), _) => RetentionPolicy::MaxAgeDays(days),
+                    (None, Some(count)) => RetentionPolicy::KeepNewest(count),
+                    (None, None) => unreachable!("clap requires a policy"),
+                };
+                let rule = RetentionRule {
+                    project_id,
+                    pattern,
+                    policy,
+                }
+                .push_into_async(&database)
+                .await?;
+                println!("Retention rule #{} created.", rule.header.id);
+            }
+            Cli::Project(ProjectCommand::Retention(RetentionCommand::List { project })) => {
+                let project_id = NamedReference::from(&project)
+                    .id_async::<Project, _>(&database)
+                    .await?
+                    .ok_or_else(|| anyhow::anyhow!("project {} not found", project))?;
+                for rule in RetentionRule::all_async(&database).await? {
+                    if rule.contents.project_id == project_id {
+                        println!(
+                            "#{}: {} {}",
+                            rule.header.id,
+                            rule.contents.pattern,
+                            retention::describe_policy(rule.contents.policy)
+                        );
+                    }
+                }
+            }
+            Cli::Project(ProjectCommand::Retention(RetentionCommand::Remove { id })) => {
+                let rule = RetentionRule::get_async(&id, &database)
+                    .await?
+                    .ok_or_else(|| anyhow::anyhow!("retention rule {id} not found"))?;
+                rule.delete_async(&database).await?;
+                println!("Retention rule #{id} deleted");
+            }
+            Cli::Project(ProjectCommand::Retention(RetentionCommand::Run { dry_run })) => {
+                let removals = retention::enforce(&database, dry_run).await?;
+                if dry_run {
+                    for removal in &removals {
+                        println!(
+                            "Would remove {} ({} files): {} (rule #{})",
+                            removal.folder, removal.files, removal.reason, removal.rule
+                        );
+                    }
+                } else {
+                    println!("{} folders removed", removals.len());
+                }
+            }
             Cli::ApiToken(ApiTokenCommand::Create { slug, label }) => {
                 let project_id = NamedReference::from(&slug)
                     .id_async::<Project, _>(&database)
@@ -193,11 +829,148 @@ impl CommandLine for CliBackend {
                 }
             }
             Cli::Compact => {
-                database.compact().await?;
+                compactor::compact(&database).await?;
+            }
+            Cli::Gc {
+                max_idle_hours,
+                dry_run,
+            } => {
+                let collected = gc::collect(&database, max_idle_hours * 60 * 60, dry_run).await?;
+                let verb = if dry_run { "Would remove" } else { "Removed" };
+                println!(
+                    "{verb} {} expired uploads and {} orphaned files ({})",
+                    collected.expired_uploads,
+                    collected.orphaned_files,
+                    format_bytes(collected.bytes)
+                );
+                let collected = gc::collect_blocks(&database, dry_run).await?;
+                println!(
+                    "{verb} {} blocks without a file ({})",
+                    collected.blocks,
+                    format_bytes(collected.bytes)
+                );
+            }
+            Cli::Status => {
+                let status = server_status(&database).await?;
+                println!("Version: {}", status.version);
+                match status.uptime_secs {
+                    Some(uptime) => {
+                        println!("Uptime: {}", format_duration(Duration::from_secs(uptime)));
+                    }
+                    None => println!("Uptime: not applicable, the database was opened locally"),
+                }
+                println!("Database size: {}", format_bytes(status.storage_bytes));
+                println!("Projects: {}", status.project_count);
+                println!(
+                    "Files: {} ({})",
+                    status.file_count,
+                    format_bytes(status.file_bytes)
+                );
+                match status.last_compaction {
+                    Some(compaction) => {
+                        let age = unix_timestamp().saturating_sub(compaction.started_at);
+                        print!(
+                            "Last compaction: {} ago, took {}",
+                            format_duration(Duration::from_secs(age)),
+                            format_duration(Duration::from_millis(compaction.duration_ms))
+                        );
+                        match compaction.error {
+                            Some(error) => println!(", failed: {error}"),
+                            None => println!(),
+                        }
+                    }
+                    None => println!("Last compaction: never"),
+                }
             }
-            Cli::Archive { destination } => {
-                backup(&database, &destination).await?;
+            Cli::Fsck { repair } => {
+                let findings = fsck::check(&database, repair).await?;
+                for finding in &findings {
+                    let repaired = if finding.repaired { " (repaired)" } else { "" };
+                    println!("{}: {}{repaired}", finding.path, finding.problem);
+                }
+                let repaired = findings.iter().filter(|finding| finding.repaired).count();
+                let unrepaired = findings.len() - repaired;
+                if findings.is_empty() {
+                    println!("No problems found");
+                } else if repaired > 0 {
+                    println!("{repaired} problems repaired");
+                }
+                if unrepaired > 0 {
+                    anyhow::bail!("{unrepaired} problems found that weren't repaired");
+                }
             }
+            Cli::Archive(args) => match args.into_command()? {
+                ArchiveCommand::Create {
+                    destination,
+                    format,
+                } => {
+                    let created = match format {
+                        ArchiveFormat::Directory => {
+                            archive::create(&database, &destination, available_parallelism())
+                                .await?
+                        }
+                        ArchiveFormat::TarZst => {
+                            archive::create_portable(&database, &destination).await?
+                        }
+                    };
+                    for warning in &created.warnings {
+                        eprintln!("warning: {warning}");
+                    }
+                    println!(
+                        "Snapshot {} created with {} files. Stored {} new blobs ({}).",
+                        created.id,
+                        created.files,
+                        created.new_blobs,
+                        format_bytes(created.new_bytes)
+                    );
+                }
+                ArchiveCommand::Restore { archive, snapshot } => {
+                    let restored = archive::restore(&database, &archive, snapshot).await?;
+                    println!(
+                        "Restored {} projects, {} API tokens, {} aliases, {} retention rules, \
+                         and {} files",
+                        restored.projects,
+                        restored.api_tokens,
+                        restored.aliases,
+                        restored.retention_rules,
+                        restored.files
+                    );
+                    if restored.api_tokens > 0 {
+                        println!(
+                            "API token secrets aren't archived. Delete and recreate the tokens \
+                             to use them."
+                        );
+                    }
+                }
+                ArchiveCommand::Verify { archive, snapshot } => {
+                    let verified = archive::verify(&archive, snapshot)?;
+                    println!(
+                        "Snapshot {} verified: {} files, {} blobs",
+                        verified.id, verified.files, verified.blobs
+                    );
+                }
+                ArchiveCommand::List { archive } => {
+                    for manifest in archive::list(&archive)? {
+                        let bytes = manifest.files.iter().map(|file| file.len).sum();
+                        println!(
+                            "{}: {} projects, {} files, {}",
+                            manifest.id,
+                            manifest.projects.len(),
+                            manifest.files.len(),
+                            format_bytes(bytes)
+                        );
+                    }
+                }
+                ArchiveCommand::Prune { archive, keep } => {
+                    let pruned = archive::prune(&archive, keep)?;
+                    println!(
+                        "Removed {} snapshots and {} blobs ({})",
+                        pruned.snapshots,
+                        pruned.blobs,
+                        format_bytes(pruned.bytes)
+                    );
+                }
+            },
         }
         Ok(())
     }
@@ -209,12 +982,8 @@ async fn upload_file(
     project: &str,
     database: &AnyDatabase<CliBackend>,
     verify_hash: Option<[u8; 32]>,
-) -> anyhow::Result<()> {
-    let mut remote_path = if !remote_path.starts_with('/') {
-        format!("/{project}/{remote_path}")
-    } else {
-        format!("/{project}{remote_path}")
-    };
+) -> anyhow::Result<String> {
+    let mut remote_path = project_path(project, remote_path);
 
     if remote_path.ends_with('/') {
         let name = location
@@ -224,50 +993,97 @@ async fn upload_file(
         remote_path.push_str(name);
     }
 
-    loop {
-        let mut verify_hash = VerificationHash::new(verify_hash);
-        let mut reader = fs::File::open(&location).await?;
+    let mut verify_hash = VerificationHash::new(verify_hash);
+    let mut reader = fs::File::open(&location).await?;
 
-        let mut scratch = vec![0; 1_048_576];
-        let mut current_len = 0;
-        let mut is_first_write = true;
-        let mut file_hash = None;
-        loop {
-            let bytes_read = reader.read(&mut scratch[current_len..]).await?;
-            current_len += bytes_read;
-            if bytes_read == 0 || current_len == scratch.len() {
-                file_hash = write_file_data(
-                    &remote_path,
-                    &scratch[..current_len],
-                    is_first_write,
-                    bytes_read == 0,
-                    database,
-                )
-                .await?;
-                verify_hash.update(&scratch[..current_len]);
-                is_first_write = false;
-                current_len = 0;
-            }
+    let upload = start_upload(&remote_path, reader.metadata().await?.len(), database).await?;
+    let mut scratch = vec![0; 1_048_576];
+    let mut current_len = 0;
+    let mut offset = 0;
+    loop {
+        let bytes_read = reader.read(&mut scratch[current_len..]).await?;
+        current_len += bytes_read;
+        // An empty file sends no chunks, leaving the session's empty staged
+        // file to be moved into place when the upload finishes.
+        if current_len > 0 && (bytes_read == 0 || current_len == scratch.len()) {
+            offset =
+                send_chunk(upload.session_id, offset, &scratch[..current_len], database).await?;
+            verify_hash.update(&scratch[..current_len]);
+            current_len = 0;
+        }
 
-            if bytes_read == 0 {
-                break;
-            }
+        if bytes_read == 0 {
+            break;
         }
+    }
+
+    let file_hash = finish_upload(upload.session_id, database).await?;
 
-        if file_hash.is_none() {
-            file_hash = write_file_data(&remote_path, &[], is_first_write, true, database).await?;
+    // A mismatch usually means the file changed after it was hashed. The
+    // caller decides whether to retry, since uploading again can't succeed
+    // against a stale `verify_hash`.
+    let verify_hash = verify_hash.finish();
+    if file_hash.as_slice() != verify_hash {
+        anyhow::bail!(
+            "upload failed to verify {remote_path}. Server: {file_hash:?}, Local: {verify_hash:?}"
+        );
+    }
+
+    Ok(remote_path)
+}
+
+/// Returns the full path of `path` within `project`.
+fn project_path(project: &str, path: &str) -> String {
+    if path.starts_with('/') {
+        format!("/{project}{path}")
+    } else {
+        format!("/{project}/{path}")
+    }
+}
+
+/// The number of times a chunk is sent before the upload is abandoned.
+const CHUNK_ATTEMPTS: u32 = 5;
+
+/// Sends `data` at `offset` in an upload session, returning the offset of the
+/// next chunk.
+///
+/// If the request fails, the server is asked how much data it has
+/// acknowledged. This determines whether the chunk was written before the
+/// failure, which can happen if the connection dropped before the response
+/// was received.
+async fn send_chunk(
+    session_id: u64,
+    offset: u64,
+    data: &[u8],
+    database: &AnyDatabase<CliBackend>,
+) -> anyhow::Result<u64> {
+    let next_offset = offset + data.len() as u64;
+    let mut attempt = 1;
+    loop {
+        let err = match upload_chunk(session_id, offset, data, database).await {
+            Ok(status) => return Ok(status.offset),
+            Err(err) => err,
+        };
+        if attempt == CHUNK_ATTEMPTS {
+            return Err(err);
         }
 
-        let verify_hash = verify_hash.finish();
-        if file_hash.as_ref().unwrap().as_slice() == verify_hash {
-            break;
-        } else {
-            println!("Upload failed to verify, trying again {remote_path}. Server: {file_hash:?}, Local: {verify_hash:?}");
+        eprintln!("Error uploading chunk at offset {offset}, retrying: {err}");
+        tokio::time::sleep(retry_delay(attempt)).await;
+        attempt += 1;
+
+        match upload_status(session_id, database).await {
+            Ok(status) if status.offset == next_offset => return Ok(next_offset),
+            Ok(status) if status.offset == offset => {}
+            Ok(status) => anyhow::bail!(
+                "upload session is at offset {}, expected {offset}",
+                status.offset
+            ),
+            // The chunk will be resent, and an offset mismatch will be
+            // resolved by the next status check.
+            Err(_) => {}
         }
     }
-
-    println!("File uploaded to {remote_path}");
-    Ok(())
 }
 
 #[allow(clippy::large_enum_variant)]
@@ -299,87 +1115,458 @@ impl VerificationHash {
     }
 }
 
+/// Returns `remote_path` with a leading and trailing slash.
+fn normalize_remote_folder(mut remote_path: String) -> String {
+    if !remote_path.starts_with('/') {
+        remote_path.insert(0, '/');
+    }
+
+    if !remote_path.ends_with('/') {
+        remote_path.push('/');
+    }
+    remote_path
+}
+
 async fn sync_directory(
     location: PathBuf,
-    mut remote_path: String,
+    remote_path: String,
     project: &str,
+    options: &SyncOptions,
     database: &AnyDatabase<CliBackend>,
 ) -> anyhow::Result<()> {
     if !location.is_dir() {
         anyhow::bail!("sync can only be used with directories");
     }
+    let started = Instant::now();
+    let mut summary = SyncSummary::default();
 
     let (hash_sender, hash_receiver) = flume::unbounded();
 
-    if !remote_path.starts_with('/') {
-        remote_path.insert(0, '/');
-    }
-
-    if !remote_path.ends_with('/') {
-        remote_path.push('/');
-    }
-
-    let mut existing_files = list_files(&format!("/{project}{remote_path}"), database).await?;
+    let remote_path = normalize_remote_folder(remote_path);
+
+    let filter = Arc::new(SyncFilter::new(
+        &location,
+        &options.exclude,
+        &options.include,
+    )?);
+    let remote_root = format!("/{project}{remote_path}");
+    let mut existing_files = list_files(&remote_root, database).await?;
+    let managed_remote_files = count_managed_files(existing_files.keys(), &remote_root, &filter);
+    let local_root_len = remote_path.len();
     let directories = Arc::new(Mutex::new(vec![(location, remote_path)]));
 
-    println!(
+    options.status(format_args!(
         "Computing local hashes. Remote has {} files.",
         existing_files.len()
-    );
-    for _ in 0..std::thread::available_parallelism().unwrap().get() * 2 {
-        tokio::task::spawn(hash_directories(directories.clone(), hash_sender.clone()));
+    ));
+    for _ in 0..options.hash_tasks() {
+        tokio::task::spawn(hash_directories(
+            directories.clone(),
+            filter.clone(),
+            local_root_len,
+            hash_sender.clone(),
+        ));
     }
     drop(hash_sender);
 
-    let (operation_sender, operation_receiver) = flume::unbounded();
-    let mut total_operations = 0;
+    let mut operations = Vec::new();
     while let Ok(result) = hash_receiver.recv_async().await {
         let file_hash = result?;
         if let Some(existing_hash) =
             existing_files.remove(&format!("/{project}{}", file_hash.remote_path))
         {
-            if existing_hash.as_slice() != file_hash.blake3 {
-                total_operations += 1;
-                operation_sender.send(SyncOperation::Replace(file_hash))?;
+            if existing_hash.as_slice() == file_hash.blake3 {
+                summary.skipped += 1;
+            } else {
+                operations.push(SyncOperation::Replace(file_hash));
             }
         } else {
-            total_operations += 1;
-            operation_sender.send(SyncOperation::Create(file_hash))?;
+            operations.push(SyncOperation::Create(file_hash));
         }
     }
 
     for (file_to_delete, _) in existing_files {
-        total_operations += 1;
-        operation_sender.send(SyncOperation::Delete(file_to_delete))?;
+        let relative_path = file_to_delete
+            .strip_prefix(&remote_root)
+            .unwrap_or(&file_to_delete);
+        if options.no_delete || !filter.should_sync(relative_path) {
+            // Files that aren't managed by this sync are left untouched.
+            continue;
+        }
+        operations.push(SyncOperation::Delete(file_to_delete));
+    }
+
+    // Deletes are only shown with their sizes, which requires asking the
+    // server for them, when the plan is going to be displayed.
+    let displayed = options.dry_run || matches!(options.format, OutputFormat::Json);
+    let remote_sizes = if displayed
+        && operations
+            .iter()
+            .any(|operation| matches!(operation, SyncOperation::Delete(_)))
+    {
+        list_remote_files(&remote_root, database)
+            .await?
+            .into_iter()
+            .map(|file| (file.path, file.len))
+            .collect()
+    } else {
+        HashMap::new()
+    };
+    let plan = SyncPlan::new(
+        project,
+        &remote_path,
+        options.dry_run,
+        &operations,
+        &remote_sizes,
+    );
+    match options.format {
+        OutputFormat::Text if options.dry_run => plan.print(),
+        OutputFormat::Text => {}
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&plan)?),
+    }
+    check_delete_limit(options.max_delete, plan.deletes, managed_remote_files)?;
+    if options.dry_run {
+        return Ok(());
+    }
+
+    let total_uploads = plan.creates + plan.replaces;
+    let (operation_sender, operation_receiver) = flume::unbounded();
+    let mut files_to_delete = Vec::new();
+    for operation in operations {
+        match operation {
+            SyncOperation::Delete(path) => files_to_delete.push(path),
+            upload => operation_sender.send(upload)?,
+        }
     }
     drop(operation_sender);
 
-    println!("Performing {total_operations} sync operations");
+    options.status(format_args!("Uploading {total_uploads} files"));
+    let upload_started = Instant::now();
+    let progress = Arc::new(Progress::new(total_uploads, options.quiet, options.format));
     let (result_sender, result_receiver) = flume::unbounded();
-    let error_counter = Arc::new(AtomicU8::default());
-    for _ in 0..std::thread::available_parallelism().unwrap().get() * 4 {
+    for _ in 0..options.upload_tasks() {
         let project = project.to_string();
-        let error_counter = error_counter.clone();
         tokio::task::spawn(perform_sync_operations(
             operation_receiver.clone(),
             result_sender.clone(),
             project,
             database.clone(),
-            error_counter,
+            options.retries,
+            progress.clone(),
         ));
     }
     drop(result_sender);
 
-    let mut completed_operations = 0;
+    let mut failures = Vec::new();
     while let Ok(result) = result_receiver.recv_async().await {
-        let file_written = result?;
-        completed_operations += 1;
-        println!("{file_written} ({completed_operations}/{total_operations})");
+        match result {
+            Ok(SyncOperation::Create(file_hash)) => {
+                summary.created += 1;
+                progress.file_completed(&file_hash.remote_path, file_hash.length);
+            }
+            Ok(SyncOperation::Replace(file_hash)) => {
+                summary.replaced += 1;
+                progress.file_completed(&file_hash.remote_path, file_hash.length);
+            }
+            Ok(SyncOperation::Delete(_)) => unreachable!("deletes aren't sent to workers"),
+            Err(failure) => {
+                progress.warn(format_args!("{}: {:#}", failure.remote_path, failure.error));
+                failures.push(failure);
+            }
+        }
+    }
+    summary.bytes_sent = progress.finish();
+    summary.upload_duration = upload_started.elapsed();
+
+    if !failures.is_empty() {
+        failures.sort_by(|a, b| a.remote_path.cmp(&b.remote_path));
+        eprintln!("{} files failed to upload:", failures.len());
+        for failure in &failures {
+            eprintln!("  {}: {:#}", failure.remote_path, failure.error);
+        }
+        if !files_to_delete.is_empty() {
+            eprintln!("Skipped deleting {} files.", files_to_delete.len());
+        }
+        summary.failed = failures.len();
+        summary.elapsed = started.elapsed();
+        eprintln!("{summary}");
+        anyhow::bail!("{} of {total_uploads} uploads failed", failures.len());
+    }
+
+    // Deletes are performed after all uploads have succeeded, in a single
+    // request so that the server can refuse to empty the folder.
+    if !files_to_delete.is_empty() {
+        summary.deleted =
+            delete_files(&remote_root, files_to_delete, options.force, database).await?;
     }
 
+    summary.elapsed = started.elapsed();
+    options.format.status(summary);
+
     Ok(())
 }
 
+/// How long the filesystem must be idle before changes are synced while
+/// watching.
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Performs a full sync, then syncs files as they are changed until the process
+/// is stopped.
+async fn watch_directory(
+    location: PathBuf,
+    remote_path: String,
+    project: &str,
+    options: &SyncOptions,
+    database: &AnyDatabase<CliBackend>,
+) -> anyhow::Result<()> {
+    let location = location.canonicalize()?;
+    let remote_path = normalize_remote_folder(remote_path);
+
+    // Start watching before the initial sync so that changes made while it is
+    // running aren't missed.
+    let (event_sender, event_receiver) = flume::unbounded();
+    let mut debouncer = new_debouncer(WATCH_DEBOUNCE, move |result| {
+        drop(event_sender.send(result));
+    })?;
+    debouncer
+        .watcher()
+        .watch(&location, RecursiveMode::Recursive)?;
+
+    sync_directory(
+        location.clone(),
+        remote_path.clone(),
+        project,
+        options,
+        database,
+    )
+    .await?;
+
+    let mut watch = WatchState {
+        filter: Arc::new(SyncFilter::new(
+            &location,
+            &options.exclude,
+            &options.include,
+        )?),
+        remote_root: format!("/{project}{remote_path}"),
+        remote_files: HashMap::new(),
+        location,
+        remote_path,
+    };
+    watch.remote_files = list_files(&watch.remote_root, database).await?;
+
+    options.status(format_args!(
+        "Watching {} for changes",
+        watch.location.display()
+    ));
+    while let Ok(result) = event_receiver.recv_async().await {
+        let changed = match result {
+            Ok(events) => events
+                .into_iter()
+                .map(|event| event.path)
+                .collect::<BTreeSet<_>>(),
+            Err(err) => {
+                eprintln!("Error watching for changes: {err}");
+                continue;
+            }
+        };
+
+        if let Err(err) = watch
+            .sync_changes(changed, project, options, database)
+            .await
+        {
+            eprintln!("Error syncing changes: {err:#}");
+        }
+    }
+
+    Ok(())
+}
+
+struct WatchState {
+    location: PathBuf,
+    remote_path: String,
+    remote_root: String,
+    filter: Arc<SyncFilter>,
+    /// The remote files and their hashes, kept up to date as changes are
+    /// synced.
+    remote_files: HashMap<String, Bytes>,
+}
+
+impl WatchState {
+    async fn sync_changes(
+        &mut self,
+        changed: BTreeSet<PathBuf>,
+        project: &str,
+        options: &SyncOptions,
+        database: &AnyDatabase<CliBackend>,
+    ) -> anyhow::Result<()> {
+        if changed.contains(&self.location.join(IGNORE_FILE_NAME)) {
+            self.filter = Arc::new(SyncFilter::new(
+                &self.location,
+                &options.exclude,
+                &options.include,
+            )?);
+        }
+
+        let mut uploads = Vec::new();
+        let mut directories = Vec::new();
+        let mut deletes = BTreeSet::new();
+        for path in changed {
+            let Some(relative_path) = path
+                .strip_prefix(&self.location)
+                .ok()
+                .and_then(relative_remote_path)
+            else {
+                continue;
+            };
+            if relative_path.is_empty() {
+                continue;
+            }
+
+            match fs::metadata(&path).await {
+                Ok(metadata) if metadata.is_dir() => {
+                    // Files in a directory that was moved into place may not
+                    // produce their own events.
+                    if !self.filter.is_excluded_directory(&relative_path) {
+                        directories.push((path, format!("{}{relative_path}/", self.remote_path)));
+                    }
+                }
+                Ok(_) => {
+                    if self.filter.should_sync(&relative_path) {
+                        let remote_path = format!("{}{relative_path}", self.remote_path);
+                        match FileHash::compute(path, remote_path).await {
+                            Ok(file_hash) => uploads.push(file_hash),
+                            Err(err) => eprintln!("Skipping {relative_path}: {err}"),
+                        }
+                    }
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                    // The path may have been a file or a directory.
+                    let remote_file = format!("{}{relative_path}", self.remote_root);
+                    let remote_folder = format!("{remote_file}/");
+                    deletes.extend(
+                        self.remote_files
+                            .keys()
+                            .filter(|remote| {
+                                (**remote == remote_file || remote.starts_with(&remote_folder))
+                                    && self.filter.should_sync(&remote[self.remote_root.len()..])
+                            })
+                            .cloned(),
+                    );
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        if !directories.is_empty() {
+            let (hash_sender, hash_receiver) = flume::unbounded();
+            hash_directories(
+                Arc::new(Mutex::new(directories)),
+                self.filter.clone(),
+                self.remote_path.len(),
+                hash_sender,
+            )
+            .await;
+            while let Ok(result) = hash_receiver.recv_async().await {
+                uploads.push(result?);
+            }
+        }
+
+        for file_hash in uploads {
+            let remote_file = format!("/{project}{}", file_hash.remote_path);
+            if self
+                .remote_files
+                .get(&remote_file)
+                .is_some_and(|hash| hash.as_slice() == file_hash.blake3)
+            {
+                continue;
+            }
+
+            let mut attempt = 0;
+            loop {
+                match perform_sync_operation(&file_hash, project, database).await {
+                    Ok(()) => break,
+                    Err(err) if attempt < options.retries => {
+                        attempt += 1;
+                        eprintln!("Error syncing {remote_file}, retrying: {err}");
+                        tokio::time::sleep(retry_delay(attempt)).await;
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+            options.status(format_args!("Uploaded {remote_file}"));
+            self.remote_files
+                .insert(remote_file, Bytes::from(file_hash.blake3.to_vec()));
+        }
+
+        if deletes.is_empty() || options.no_delete {
+            return Ok(());
+        }
+        self.check_delete_limit(options.max_delete, deletes.len())?;
+        let deletes = deletes.into_iter().collect::<Vec<_>>();
+        let deleted =
+            delete_files(&self.remote_root, deletes.clone(), options.force, database).await?;
+        for path in &deletes {
+            self.remote_files.remove(path);
+        }
+        options.status(format_args!("Deleted {deleted} files"));
+
+        Ok(())
+    }
+
+    /// Checks that deleting `deletes` files stays within `max_delete`, which is
+    /// measured against the remote files this sync manages, like a full sync.
+    fn check_delete_limit(
+        &self,
+        max_delete: Option<DeleteLimit>,
+        deletes: usize,
+    ) -> anyhow::Result<()> {
+        let managed_remote_files =
+            count_managed_files(self.remote_files.keys(), &self.remote_root, &self.filter);
+        check_delete_limit(max_delete, deletes, managed_remote_files)
+    }
+}
+
+/// Returns how many of `remote_files` within `remote_root` are synced by
+/// `filter`. Only these files can be deleted by a sync.
+fn count_managed_files<'a>(
+    remote_files: impl IntoIterator<Item = &'a String>,
+    remote_root: &str,
+    filter: &SyncFilter,
+) -> usize {
+    remote_files
+        .into_iter()
+        .filter(|path| filter.should_sync(path.strip_prefix(remote_root).unwrap_or(path)))
+        .count()
+}
+
+/// Returns an error if deleting `deletes` of `managed_remote_files` exceeds
+/// `max_delete`.
+fn check_delete_limit(
+    max_delete: Option<DeleteLimit>,
+    deletes: usize,
+    managed_remote_files: usize,
+) -> anyhow::Result<()> {
+    match max_delete {
+        Some(max_delete) if !max_delete.allows(deletes, managed_remote_files) => anyhow::bail!(
+            "refusing to delete {deletes} of {managed_remote_files} remote files, which exceeds --max-delete {max_delete}"
+        ),
+        _ => Ok(()),
+    }
+}
+
+/// Converts a path relative to the root of a sync into the form used for
+/// remote paths. Returns None if the path isn't valid UTF-8.
+fn relative_remote_path(path: &Path) -> Option<String> {
+    let mut relative = String::new();
+    for component in path.components() {
+        if !relative.is_empty() {
+            relative.push('/');
+        }
+        relative.push_str(component.as_os_str().to_str()?);
+    }
+    Some(relative)
+}
+
 async fn list_files(
     remote_path: &str,
     database: &AnyDatabase<CliBackend>,
@@ -407,10 +1594,132 @@ pub struct FileHash {
     path: PathBuf,
     remote_path: String,
     blake3: [u8; 32],
+    length: u64,
+}
+
+impl FileHash {
+    async fn compute(path: PathBuf, remote_path: String) -> anyhow::Result<Self> {
+        let mut hasher = blake3::Hasher::new();
+        let mut file = fs::File::open(&path).await?;
+        let mut scratch = [0; 16 * 1024];
+        let mut length = 0;
+        loop {
+            let bytes_read = file.read(&mut scratch).await?;
+            if bytes_read > 0 {
+                hasher.update(&scratch[..bytes_read]);
+                length += bytes_read as u64;
+            } else {
+                break;
+            }
+        }
+        Ok(FileHash {
+            path,
+            remote_path,
+            blake3: hasher.finalize().into(),
+            length,
+        })
+    }
+}
+
+/// The operations a sync will perform, in a form that can be printed or
+/// serialized for other tools to consume.
+#[derive(Debug, Serialize)]
+struct SyncPlan {
+    project: String,
+    remote_path: String,
+    dry_run: bool,
+    operations: Vec<PlannedOperation>,
+    creates: usize,
+    replaces: usize,
+    deletes: usize,
+    /// The total size of the files that will be uploaded.
+    upload_bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct PlannedOperation {
+    operation: &'static str,
+    path: String,
+    /// The size of the local file being uploaded, or of the remote file being
+    /// deleted. Deletes have no size if the remote file's size wasn't looked
+    /// up.
+    size: Option<u64>,
+}
+
+impl SyncPlan {
+    /// Builds the plan for `operations`. The sizes of deleted files are looked
+    /// up in `remote_sizes`, which maps remote paths to their lengths.
+    fn new(
+        project: &str,
+        remote_path: &str,
+        dry_run: bool,
+        operations: &[SyncOperation],
+        remote_sizes: &HashMap<String, u64>,
+    ) -> Self {
+        let mut plan = Self {
+            project: project.to_string(),
+            remote_path: remote_path.to_string(),
+            dry_run,
+            operations: Vec::with_capacity(operations.len()),
+            creates: 0,
+            replaces: 0,
+            deletes: 0,
+            upload_bytes: 0,
+        };
+        for operation in operations {
+            let (operation, path, size) = match operation {
+                SyncOperation::Create(file) => {
+                    plan.creates += 1;
+                    plan.upload_bytes += file.length;
+                    (
+                        "create",
+                        format!("/{project}{}", file.remote_path),
+                        Some(file.length),
+                    )
+                }
+                SyncOperation::Replace(file) => {
+                    plan.replaces += 1;
+                    plan.upload_bytes += file.length;
+                    (
+                        "replace",
+                        format!("/{project}{}", file.remote_path),
+                        Some(file.length),
+                    )
+                }
+                SyncOperation::Delete(path) => {
+                    plan.deletes += 1;
+                    ("delete", path.clone(), remote_sizes.get(path).copied())
+                }
+            };
+            plan.operations.push(PlannedOperation {
+                operation,
+                path,
+                size,
+            });
+        }
+        plan.operations.sort_by(|a, b| a.path.cmp(&b.path));
+        plan
+    }
+
+    fn print(&self) {
+        for operation in &self.operations {
+            let size = operation.size.map(format_bytes).unwrap_or_default();
+            println!("{:<8}{size:>12}  {}", operation.operation, operation.path);
+        }
+        println!(
+            "{} to create, {} to replace, {} to delete, {} to upload",
+            self.creates,
+            self.replaces,
+            self.deletes,
+            format_bytes(self.upload_bytes)
+        );
+    }
 }
 
 async fn hash_directories(
     directories: Arc<Mutex<Vec<(PathBuf, String)>>>,
+    filter: Arc<SyncFilter>,
+    root_len: usize,
     result_sender: flume::Sender<anyhow::Result<FileHash>>,
 ) {
     loop {
@@ -422,8 +1731,15 @@ async fn hash_directories(
             }
         };
 
-        if let Err(err) =
-            check_directory(directory, remote_path, &directories, &result_sender).await
+        if let Err(err) = check_directory(
+            directory,
+            remote_path,
+            &directories,
+            &filter,
+            root_len,
+            &result_sender,
+        )
+        .await
         {
             drop(result_sender.send(Err(err)));
             break;
@@ -431,10 +1747,15 @@ async fn hash_directories(
     }
 }
 
+/// Hashes the files in `directory` and queues its subdirectories.
+/// `root_len` is the length of the remote path of the root of the sync, which
+/// is used to compute the relative paths checked against `filter`.
 async fn check_directory(
     directory: PathBuf,
     remote_path: String,
     directories: &Mutex<Vec<(PathBuf, String)>>,
+    filter: &SyncFilter,
+    root_len: usize,
     result_sender: &flume::Sender<anyhow::Result<FileHash>>,
 ) -> anyhow::Result<()> {
     let mut contents = tokio::fs::read_dir(&directory).await?;
@@ -450,53 +1771,63 @@ async fn check_directory(
 
         if file_type.is_dir() {
             let destination_path = format!("{remote_path}{name}/");
+            if filter.is_excluded_directory(&destination_path[root_len..]) {
+                continue;
+            }
             let mut directories = directories.lock();
             directories.push((entry.path(), destination_path));
         } else {
             let remote_path = format!("{remote_path}{name}");
-            let path = entry.path();
-
-            let mut hasher = blake3::Hasher::new();
-            let mut file = fs::File::open(&path).await?;
-            let mut scratch = [0; 16 * 1024];
-            loop {
-                let bytes_read = file.read(&mut scratch).await?;
-                if bytes_read > 0 {
-                    hasher.update(&scratch[..bytes_read]);
-                } else {
-                    break;
-                }
+            if !filter.should_sync(&remote_path[root_len..]) {
+                continue;
             }
-            result_sender.send(Ok(FileHash {
-                path,
-                remote_path,
-                blake3: hasher.finalize().try_into().unwrap(),
-            }))?;
+            result_sender.send(Ok(FileHash::compute(entry.path(), remote_path).await?))?;
         }
     }
 
     Ok(())
 }
 
+/// An upload that failed after exhausting its retries.
+struct SyncFailure {
+    remote_path: String,
+    error: anyhow::Error,
+}
+
+/// Uploads the files from `operations`, which must only contain creates and
+/// replaces, returning each operation once it succeeds.
 async fn perform_sync_operations(
     operations: flume::Receiver<SyncOperation>,
-    result_sender: flume::Sender<anyhow::Result<String>>,
+    result_sender: flume::Sender<Result<SyncOperation, SyncFailure>>,
     project: String,
     database: AnyDatabase<CliBackend>,
-    error_counter: Arc<AtomicU8>,
+    retries: u32,
+    progress: Arc<Progress>,
 ) {
     while let Ok(op) = operations.recv_async().await {
+        let (SyncOperation::Create(file_hash) | SyncOperation::Replace(file_hash)) = &op else {
+            unreachable!("deletes aren't sent to workers")
+        };
+        let mut attempt = 0;
         let result = loop {
-            match perform_sync_operation(&op, &project, &database).await {
-                Ok(result) => {
-                    error_counter.store(0, atomic::Ordering::SeqCst);
-                    break Ok(result);
+            match perform_sync_operation(file_hash, &project, &database).await {
+                Ok(()) => break Ok(op),
+                Err(err) if attempt < retries => {
+                    attempt += 1;
+                    let delay = retry_delay(attempt);
+                    progress.warn(format_args!(
+                        "error syncing {}, retrying in {:.1}s ({attempt}/{retries}): {err}",
+                        file_hash.remote_path,
+                        delay.as_secs_f32()
+                    ));
+                    tokio::time::sleep(delay).await;
                 }
-                Err(err) if error_counter.fetch_add(1, atomic::Ordering::SeqCst) < 5 => {
-                    eprintln!("error encountered during sync, but retrying: {err}");
-                    continue;
+                Err(error) => {
+                    break Err(SyncFailure {
+                        remote_path: file_hash.remote_path.clone(),
+                        error,
+                    })
                 }
-                Err(err) => break Err(err),
             }
         };
         if result_sender.send(result).is_err() {
@@ -505,134 +1836,313 @@ async fn perform_sync_operations(
     }
 }
 
+/// Returns how long to wait before retry number `attempt`, which starts at 1.
+///
+/// The delay grows exponentially up to a limit, and a random amount of
+/// jitter is applied so that concurrent operations that failed at the same
+/// time don't retry in lockstep.
+fn retry_delay(attempt: u32) -> Duration {
+    const BASE: Duration = Duration::from_millis(250);
+    const MAX: Duration = Duration::from_secs(30);
+    let ceiling = BASE
+        .saturating_mul(2_u32.saturating_pow(attempt.saturating_sub(1)))
+        .min(MAX);
+    ceiling.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+}
+
 async fn perform_sync_operation(
-    operation: &SyncOperation,
+    file_hash: &FileHash,
     project: &str,
     database: &AnyDatabase<CliBackend>,
-) -> anyhow::Result<String> {
-    match operation {
-        SyncOperation::Create(file_hash) => {
-            upload_file(
-                &file_hash.path,
-                &file_hash.remote_path,
-                project,
-                database,
-                Some(file_hash.blake3),
-            )
-            .await?;
-            Ok(file_hash.remote_path.clone())
-        }
-        SyncOperation::Replace(file_hash) => {
-            upload_file(
-                &file_hash.path,
-                &file_hash.remote_path,
-                project,
-                database,
-                Some(file_hash.blake3),
-            )
-            .await?;
+) -> anyhow::Result<()> {
+    upload_file(
+        &file_hash.path,
+        &file_hash.remote_path,
+        project,
+        database,
+        Some(file_hash.blake3),
+    )
+    .await?;
+    Ok(())
+}
 
-            Ok(file_hash.remote_path.clone())
-        }
-        SyncOperation::Delete(file) => {
-            delete_file(file, database).await?;
+async fn delete_files(
+    root: &str,
+    paths: Vec<String>,
+    force: bool,
+    database: &AnyDatabase<CliBackend>,
+) -> anyhow::Result<usize> {
+    match database {
+        AnyDatabase::Local(database) => Ok(api::delete_files(root, &paths, force, database).await?),
+        AnyDatabase::Networked(client) => Ok(client
+            .storage()
+            .send_api_request(&DeleteFiles {
+                root: root.to_string(),
+                paths,
+                force,
+            })
+            .await?),
+    }
+}
+
+async fn copy_files(
+    source: String,
+    destination: String,
+    database: &AnyDatabase<CliBackend>,
+) -> anyhow::Result<usize> {
+    match database {
+        AnyDatabase::Local(database) => Ok(api::copy_files(&source, &destination, database).await?),
+        AnyDatabase::Networked(client) => Ok(client
+            .storage()
+            .send_api_request(&CopyFiles {
+                source,
+                destination,
+            })
+            .await?),
+    }
+}
+
+async fn move_files(
+    source: String,
+    destination: String,
+    database: &AnyDatabase<CliBackend>,
+) -> anyhow::Result<usize> {
+    match database {
+        AnyDatabase::Local(database) => Ok(api::move_files(&source, &destination, database).await?),
+        AnyDatabase::Networked(client) => Ok(client
+            .storage()
+            .send_api_request(&MoveFiles {
+                source,
+                destination,
+            })
+            .await?),
+    }
+}
+
+async fn set_alias(
+    path: &str,
+    target: &str,
+    redirect: bool,
+    database: &AnyDatabase<CliBackend>,
+) -> anyhow::Result<()> {
+    match database {
+        AnyDatabase::Local(database) => Ok(api::set_alias(path, target, redirect, database).await?),
+        AnyDatabase::Networked(client) => Ok(client
+            .storage()
+            .send_api_request(&SetAlias {
+                path: path.to_string(),
+                target: target.to_string(),
+                redirect,
+            })
+            .await?),
+    }
+}
 
-            Ok(file.clone())
+async fn delete_alias(path: &str, database: &AnyDatabase<CliBackend>) -> anyhow::Result<bool> {
+    match database {
+        AnyDatabase::Local(database) => Ok(api::delete_alias(path, database).await?),
+        AnyDatabase::Networked(client) => Ok(client
+            .storage()
+            .send_api_request(&DeleteAlias {
+                path: path.to_string(),
+            })
+            .await?),
+    }
+}
+
+async fn list_aliases(
+    project: &str,
+    database: &AnyDatabase<CliBackend>,
+) -> anyhow::Result<Vec<Alias>> {
+    match database {
+        AnyDatabase::Local(database) => Ok(api::list_aliases(project, database).await?),
+        AnyDatabase::Networked(client) => Ok(client
+            .storage()
+            .send_api_request(&ListAliases {
+                project: project.to_string(),
+            })
+            .await?),
+    }
+}
+
+async fn list_remote_files(
+    path: &str,
+    database: &AnyDatabase<CliBackend>,
+) -> anyhow::Result<Vec<RemoteFile>> {
+    match database {
+        AnyDatabase::Local(database) => Ok(api::list_remote_files(path, database).await?),
+        AnyDatabase::Networked(client) => Ok(client
+            .storage()
+            .send_api_request(&ListRemoteFiles {
+                path: path.to_string(),
+            })
+            .await?),
+    }
+}
+
+async fn stat_file(
+    path: &str,
+    database: &AnyDatabase<CliBackend>,
+) -> anyhow::Result<Option<RemoteFile>> {
+    match database {
+        AnyDatabase::Local(database) => Ok(api::stat_file(path, database).await?),
+        AnyDatabase::Networked(client) => Ok(client
+            .storage()
+            .send_api_request(&StatFile {
+                path: path.to_string(),
+            })
+            .await?),
+    }
+}
+
+async fn read_file(
+    path: &str,
+    offset: u64,
+    len: u64,
+    database: &AnyDatabase<CliBackend>,
+) -> anyhow::Result<Bytes> {
+    match database {
+        AnyDatabase::Local(database) => Ok(api::read_file(path, offset, len, database).await?),
+        AnyDatabase::Networked(client) => Ok(client
+            .storage()
+            .send_api_request(&ReadFile {
+                path: path.to_string(),
+                offset,
+                len,
+            })
+            .await?),
+    }
+}
+
+/// Returns the status of the server. Uptime isn't reported when the database
+/// was opened locally rather than through a running server.
+async fn server_status(database: &AnyDatabase<CliBackend>) -> anyhow::Result<ServerStatus> {
+    match database {
+        AnyDatabase::Local(database) => Ok(api::database_status(None, database).await?),
+        AnyDatabase::Networked(client) => {
+            Ok(client.storage().send_api_request(&GetServerStatus).await?)
         }
     }
 }
 
-async fn delete_file(
-    remote_path: &str,
+async fn folder_usage(
+    path: &str,
+    depth: usize,
     database: &AnyDatabase<CliBackend>,
-) -> anyhow::Result<bool> {
+) -> anyhow::Result<Vec<FolderUsage>> {
     match database {
-        AnyDatabase::Local(database) => Ok(api::delete_file(remote_path, database).await?),
+        AnyDatabase::Local(database) => Ok(api::folder_usage(path, depth, database).await?),
         AnyDatabase::Networked(client) => Ok(client
             .storage()
-            .send_api_request(&DeleteFile {
-                path: remote_path.to_string(),
+            .send_api_request(&GetUsage {
+                path: path.to_string(),
+                depth,
             })
             .await?),
     }
 }
 
-async fn write_file_data(
+async fn start_upload(
     path: &str,
+    len: u64,
+    database: &AnyDatabase<CliBackend>,
+) -> anyhow::Result<UploadStatus> {
+    match database {
+        AnyDatabase::Local(database) => Ok(api::start_upload(path, len, database).await?),
+        AnyDatabase::Networked(client) => Ok(client
+            .storage()
+            .send_api_request(&StartUpload {
+                path: path.to_string(),
+                len,
+            })
+            .await?),
+    }
+}
+
+async fn upload_chunk(
+    session_id: u64,
+    offset: u64,
     data: &[u8],
-    start: bool,
-    finished: bool,
     database: &AnyDatabase<CliBackend>,
-) -> anyhow::Result<Option<Bytes>> {
+) -> anyhow::Result<UploadStatus> {
     match database {
         AnyDatabase::Local(database) => {
-            Ok(api::write_file_data(path, data, start, finished, database).await?)
+            let upload = api::load_upload_session(session_id, database).await?;
+            Ok(api::upload_chunk(upload, offset, data, database).await?)
         }
         AnyDatabase::Networked(client) => Ok(client
             .storage()
-            .send_api_request(&WriteFileData {
-                path: path.to_string(),
+            .send_api_request(&UploadChunk {
+                session_id,
+                offset,
                 data: Bytes::from(data),
-                start,
-                finished,
             })
             .await?),
     }
 }
 
-async fn backup(database: &AnyDatabase<CliBackend>, destination: &Path) -> anyhow::Result<()> {
-    if !destination.exists() {
-        std::fs::create_dir_all(destination)?;
+async fn upload_status(
+    session_id: u64,
+    database: &AnyDatabase<CliBackend>,
+) -> anyhow::Result<UploadStatus> {
+    match database {
+        AnyDatabase::Local(database) => {
+            let upload = api::load_upload_session(session_id, database).await?;
+            Ok(api::upload_status(upload, database).await?)
+        }
+        AnyDatabase::Networked(client) => Ok(client
+            .storage()
+            .send_api_request(&GetUploadStatus { session_id })
+            .await?),
+    }
+}
+
+async fn finish_upload(
+    session_id: u64,
+    database: &AnyDatabase<CliBackend>,
+) -> anyhow::Result<Bytes> {
+    match database {
+        AnyDatabase::Local(database) => {
+            let upload = api::load_upload_session(session_id, database).await?;
+            Ok(api::finish_upload(upload, database).await?)
+        }
+        AnyDatabase::Networked(client) => Ok(client
+            .storage()
+            .send_api_request(&FinishUpload { session_id })
+            .await?),
     }
+}
 
-    let files = DossierFiles::list_recursive_async("/", database).await?;
+/// Downloads `files` into `destination`, recreating their paths relative to
+/// `remote_root`, which must end with a `/`.
+///
+/// Files whose local copy already matches are skipped, and downloaded contents
+/// are verified against the hash the server has stored.
+async fn download_files(
+    files: Vec<RemoteFile>,
+    remote_root: &str,
+    destination: &Path,
+    database: &AnyDatabase<CliBackend>,
+) -> anyhow::Result<()> {
     let mut tasks = Vec::new();
-    let number_of_tasks = std::thread::available_parallelism().map_or(8, |t| t.get());
-    let (sender, receiver) =
-        flume::bounded::<File<Async<AnyDatabase<CliBackend>>, DossierFiles>>(number_of_tasks);
+    let number_of_tasks = available_parallelism();
+    let (sender, receiver) = flume::bounded::<RemoteFile>(number_of_tasks);
 
     for _ in 0..number_of_tasks {
         let receiver = receiver.clone();
         let folder = destination.to_path_buf();
+        let remote_root = remote_root.to_string();
+        let database = database.clone();
         tasks.push(tokio::spawn(async move {
             let mut file_contents = Vec::new();
             while let Ok(file) = receiver.recv_async().await {
-                let mut folder = folder.clone();
-                for intermediate_name in file.containing_path().split_terminator('/').skip(1) {
-                    folder.push(intermediate_name);
-                }
-                if !folder.exists() {
-                    std::fs::create_dir_all(&folder)?;
-                }
-
-                let file_path = folder.join(file.name());
-                if file_path.exists() {
-                    // Check that the file hash doesn't match before re-downloading.
-                    let mut hasher = blake3::Hasher::new();
-                    let mut existing_file = fs::File::open(&file_path).await?;
-                    let mut scratch = [0; 16 * 1024];
-                    loop {
-                        let bytes_read = existing_file.read(&mut scratch).await?;
-                        if bytes_read > 0 {
-                            hasher.update(&scratch[..bytes_read]);
-                        } else {
-                            break;
-                        }
-                    }
-                    let hash = hasher.finalize().try_into().unwrap();
-                    if file.metadata().map(|m| m.blake3) == Some(hash) {
-                        println!("Skipping {}{}", file.containing_path(), file.name());
-                        continue;
-                    }
-                }
-
-                let mut contents = file.contents().await?;
-
-                file_contents.clear();
-                contents.read_to_end(&mut file_contents).await?;
+                let relative_path = file
+                    .path
+                    .strip_prefix(remote_root.as_str())
+                    .unwrap_or(&file.path);
+                let destination = local_path(&folder, relative_path)?;
 
-                println!("Downloading {}{}", file.containing_path(), file.name());
-                std::fs::write(file_path, &file_contents)?;
+                download_file(&file, &destination, &mut file_contents, &database).await?;
             }
 
             anyhow::Ok(())
@@ -649,17 +2159,270 @@ async fn backup(database: &AnyDatabase<CliBackend>, destination: &Path) -> anyho
         task.await??;
     }
 
-    let projects = Project::all_async(database).await?;
-    std::fs::write(
-        destination.join("projects.ron"),
-        ron::Options::default().to_string_pretty(&projects, PrettyConfig::default())?,
-    )?;
+    Ok(())
+}
+
+/// Returns the path of the file `relative_path`, which came from the server,
+/// within `folder`. Segments that could write outside of `folder` are rejected.
+fn local_path(folder: &Path, relative_path: &str) -> anyhow::Result<PathBuf> {
+    let mut path = folder.to_path_buf();
+    for segment in relative_path.split('/') {
+        let mut components = Path::new(segment).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(_)), None) => path.push(segment),
+            _ => anyhow::bail!("invalid path from server: {relative_path:?}"),
+        }
+    }
+    Ok(path)
+}
+
+/// Downloads `file` to `destination`, using `buffer` to hold its contents.
+/// Nothing is downloaded if `destination` already has the same contents.
+async fn download_file(
+    file: &RemoteFile,
+    destination: &Path,
+    buffer: &mut Vec<u8>,
+    database: &AnyDatabase<CliBackend>,
+) -> anyhow::Result<()> {
+    if destination.exists() {
+        // Check that the file hash doesn't match before re-downloading.
+        let local = FileHash::compute(destination.to_path_buf(), String::new()).await?;
+        if file.blake3 == Some(local.blake3) {
+            println!("Skipping {}", file.path);
+            return Ok(());
+        }
+    }
 
-    let api_tokens = ApiToken::all_async(database).await?;
-    std::fs::write(
-        destination.join("api-tokens.ron"),
-        ron::Options::default().to_string_pretty(&api_tokens, PrettyConfig::default())?,
-    )?;
+    buffer.clear();
+    read_remote_file(&file.path, database, |data| {
+        buffer.extend_from_slice(data);
+        Ok(())
+    })
+    .await?;
 
+    if let Some(expected_hash) = file.blake3 {
+        if blake3::hash(buffer) != expected_hash {
+            anyhow::bail!("{} did not match its hash after downloading", file.path);
+        }
+    }
+
+    println!("Downloading {}", file.path);
+    if let Some(parent) = destination.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(destination, buffer)?;
     Ok(())
 }
+
+/// Reads the remote file at `path`, passing its contents to `on_data` in
+/// pieces of up to [`MAX_READ_LEN`] bytes.
+async fn read_remote_file(
+    path: &str,
+    database: &AnyDatabase<CliBackend>,
+    mut on_data: impl FnMut(&[u8]) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let mut offset = 0;
+    loop {
+        let data = read_file(path, offset, MAX_READ_LEN, database).await?;
+        if !data.is_empty() {
+            on_data(&data)?;
+        }
+        // A short read means the end of the file was reached.
+        if (data.len() as u64) < MAX_READ_LEN {
+            return Ok(());
+        }
+        offset += data.len() as u64;
+    }
+}
+
+/// Downloads the remote file or folder at `remote_path` to `location`.
+async fn download(
+    project: &str,
+    remote_path: &str,
+    location: &Path,
+    database: &AnyDatabase<CliBackend>,
+) -> anyhow::Result<()> {
+    let remote_path = project_path(project, remote_path);
+    if !remote_path.ends_with('/') {
+        if let Some(file) = stat_file(&remote_path, database).await? {
+            let destination = if location.is_dir() {
+                location.join(file.path.rsplit('/').next().unwrap_or_default())
+            } else {
+                location.to_path_buf()
+            };
+            download_file(&file, &destination, &mut Vec::new(), database).await?;
+            return Ok(());
+        }
+    }
+
+    let folder = normalize_remote_folder(remote_path);
+    let files = list_remote_files(&folder, database).await?;
+    if files.is_empty() {
+        anyhow::bail!("{folder} not found");
+    }
+    download_files(files, &folder, location, database).await
+}
+
+/// Prints the files in the remote folder `path`. Unless `recursive` is set,
+/// subfolders are listed by name rather than by their contents.
+async fn list_remote_folder(
+    project: &str,
+    path: &str,
+    recursive: bool,
+    database: &AnyDatabase<CliBackend>,
+) -> anyhow::Result<()> {
+    let folder = normalize_remote_folder(project_path(project, path));
+    let files = list_remote_files(&folder, database).await?;
+
+    let mut subfolders = BTreeSet::new();
+    for file in files {
+        let relative_path = &file.path[folder.len()..];
+        if !recursive {
+            if let Some((subfolder, _)) = relative_path.split_once('/') {
+                subfolders.insert(subfolder.to_string());
+                continue;
+            }
+        }
+        println!("{:>12}  {relative_path}", format_bytes(file.len));
+    }
+    for subfolder in subfolders {
+        println!("{:>12}  {subfolder}/", "-");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file_hash(remote_path: &str, length: u64) -> FileHash {
+        FileHash {
+            path: PathBuf::from(remote_path),
+            remote_path: remote_path.to_string(),
+            blake3: [0; 32],
+            length,
+        }
+    }
+
+    #[test]
+    fn delete_limit_count() {
+        let limit = "10".parse::<DeleteLimit>().unwrap();
+        assert_eq!(limit, DeleteLimit::Count(10));
+        assert!(limit.allows(10, 20));
+        assert!(!limit.allows(11, 1000));
+        assert_eq!(limit.to_string(), "10");
+    }
+
+    #[test]
+    fn delete_limit_percent() {
+        let limit = "5%".parse::<DeleteLimit>().unwrap();
+        assert_eq!(limit, DeleteLimit::Percent(5.));
+        assert!(limit.allows(5, 100));
+        assert!(!limit.allows(6, 100));
+        // With no remote files, there is nothing to take a percentage of.
+        assert!(limit.allows(0, 0));
+        assert_eq!(limit.to_string(), "5%");
+        assert_eq!("100%".parse(), Ok(DeleteLimit::Percent(100.)));
+    }
+
+    #[test]
+    fn delete_limit_zero() {
+        let limit = "0".parse::<DeleteLimit>().unwrap();
+        assert!(limit.allows(0, 10));
+        assert!(!limit.allows(1, 10));
+
+        let limit = "0%".parse::<DeleteLimit>().unwrap();
+        assert!(limit.allows(0, 10));
+        assert!(!limit.allows(1, 10));
+    }
+
+    #[test]
+    fn delete_limit_invalid() {
+        for invalid in [
+            "101%", "250%", "-1%", "NaN%", "%", "", "-1", "1.5", "ten", "5 %", "5%%",
+        ] {
+            assert!(
+                invalid.parse::<DeleteLimit>().is_err(),
+                "{invalid:?} should not parse"
+            );
+        }
+    }
+
+    #[test]
+    fn watch_delete_limit() {
+        let watch = WatchState {
+            location: PathBuf::from("/nonexistent"),
+            remote_path: String::from("/"),
+            remote_root: String::from("/project/"),
+            filter: Arc::new(
+                SyncFilter::new(Path::new("/nonexistent"), &[String::from("*.log")], &[]).unwrap(),
+            ),
+            remote_files: [
+                "a", "b", "1.log", "2.log", "3.log", "4.log", "5.log", "6.log",
+            ]
+            .into_iter()
+            .map(|name| (format!("/project/{name}"), Bytes::from(Vec::new())))
+            .collect(),
+        };
+
+        // The percentage is of the two managed files, not of every remote
+        // file.
+        let limit = Some(DeleteLimit::Percent(50.));
+        watch.check_delete_limit(limit, 1).unwrap();
+        let err = watch.check_delete_limit(limit, 2).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "refusing to delete 2 of 2 remote files, which exceeds --max-delete 50%"
+        );
+
+        watch
+            .check_delete_limit(Some(DeleteLimit::Count(2)), 2)
+            .unwrap();
+        watch.check_delete_limit(None, 2).unwrap();
+    }
+
+    #[test]
+    fn sync_plan() {
+        let operations = [
+            SyncOperation::Replace(file_hash("/b", 20)),
+            SyncOperation::Delete(String::from("/project/c")),
+            SyncOperation::Create(file_hash("/a", 10)),
+            SyncOperation::Delete(String::from("/project/d")),
+        ];
+        let remote_sizes = HashMap::from([(String::from("/project/c"), 30)]);
+        let plan = SyncPlan::new("project", "/", true, &operations, &remote_sizes);
+
+        assert_eq!(plan.creates, 1);
+        assert_eq!(plan.replaces, 1);
+        assert_eq!(plan.deletes, 2);
+        // Deleted files aren't uploaded.
+        assert_eq!(plan.upload_bytes, 30);
+        let operations = plan
+            .operations
+            .iter()
+            .map(|operation| (operation.operation, operation.path.as_str(), operation.size))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            operations,
+            [
+                ("create", "/project/a", Some(10)),
+                ("replace", "/project/b", Some(20)),
+                ("delete", "/project/c", Some(30)),
+                ("delete", "/project/d", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn local_paths() {
+        let folder = Path::new("downloads");
+        assert_eq!(
+            local_path(folder, "a/b.html").unwrap(),
+            folder.join("a").join("b.html")
+        );
+        for invalid in ["../a", "a/../../b", "./a", "a//b", "/a", "a/", ""] {
+            assert!(local_path(folder, invalid).is_err(), "{invalid}");
+        }
+    }
+}
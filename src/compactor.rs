@@ -1,17 +1,282 @@
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use bonsaidb::{core::connection::AsyncConnection, server::ServerDatabase};
+use bonsaidb::{
+    core::{connection::AsyncConnection, schema::SerializedCollection},
+    files::FileConfig,
+    server::ServerDatabase,
+};
 
-use crate::CliBackend;
+use crate::{
+    access_log::env_or,
+    api::unix_timestamp,
+    gc,
+    metrics::metrics,
+    progress::format_bytes,
+    schema::{Compaction, DossierFiles},
+    uploads::MAX_IDLE,
+    CliBackend,
+};
 
-pub(crate) fn launch(dossier: ServerDatabase<CliBackend>) {
+/// How often the schedule is checked.
+const CHECK_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// When the database is compacted, configured from the environment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Schedule {
+    /// The minimum time between compactions.
+    interval: Duration,
+    /// The time of day, in UTC, that compaction may run.
+    window: Option<Window>,
+    /// Compaction is skipped until at least this many files have been written
+    /// or deleted since the last compaction.
+    min_changes: usize,
+}
+
+impl Schedule {
+    fn from_env() -> anyhow::Result<Self> {
+        let window = match std::env::var("COMPACTION_WINDOW") {
+            Ok(window) => Some(window.parse().map_err(|_| {
+                anyhow::anyhow!("invalid COMPACTION_WINDOW, expected HH:MM-HH:MM: {window}")
+            })?),
+            Err(_) => None,
+        };
+        Ok(Self {
+            interval: Duration::from_secs(env_or("COMPACTION_INTERVAL_HOURS", 24)? * 60 * 60),
+            window,
+            min_changes: env_or("COMPACTION_MIN_CHANGES", 1)?,
+        })
+    }
+
+    /// Returns true if a compaction may start at `now` when the previous one
+    /// started at `last`. Both are in seconds since the Unix epoch.
+    fn is_due(&self, last: Option<u64>, now: u64) -> bool {
+        match (last, self.window) {
+            (None, None) => true,
+            (None, Some(window)) => window.contains(now),
+            (Some(last), None) => now.saturating_sub(last) >= self.interval.as_secs(),
+            // A compaction that ran late in the previous window shouldn't push
+            // this one past the end of the window, so the window's length is
+            // subtracted from the interval.
+            (Some(last), Some(window)) => {
+                window.contains(now)
+                    && now.saturating_sub(last) + window.len_secs() >= self.interval.as_secs()
+            }
+        }
+    }
+}
+
+/// A daily range of time, in minutes since midnight UTC. The range wraps
+/// around midnight when `end` is before `start`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Window {
+    start: u32,
+    end: u32,
+}
+
+impl Window {
+    fn len_secs(&self) -> u64 {
+        let minutes = if self.start <= self.end {
+            self.end - self.start
+        } else {
+            24 * 60 - self.start + self.end
+        };
+        u64::from(minutes) * 60
+    }
+
+    fn contains(&self, timestamp: u64) -> bool {
+        let minute = u32::try_from(timestamp % (24 * 60 * 60) / 60).expect("less than a day");
+        if self.start <= self.end {
+            (self.start..self.end).contains(&minute)
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+}
+
+impl std::str::FromStr for Window {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        fn parse_time(time: &str) -> Result<u32, ()> {
+            let (hours, minutes) = time.trim().split_once(':').ok_or(())?;
+            let hours = hours.parse::<u32>().map_err(|_| ())?;
+            let minutes = minutes.parse::<u32>().map_err(|_| ())?;
+            if hours < 24 && minutes < 60 {
+                Ok(hours * 60 + minutes)
+            } else {
+                Err(())
+            }
+        }
+
+        let (start, end) = s.split_once('-').ok_or(())?;
+        Ok(Self {
+            start: parse_time(start)?,
+            end: parse_time(end)?,
+        })
+    }
+}
+
+pub(crate) fn launch(dossier: ServerDatabase<CliBackend>) -> anyhow::Result<()> {
+    let schedule = Schedule::from_env()?;
     tokio::spawn(async move {
+        // Checks that found too few changes count as a run, so that the
+        // files aren't rescanned until the next interval.
+        let mut last_checked = None;
         loop {
-            tokio::time::sleep(Duration::from_secs(24 * 60 * 60)).await;
-            println!("Compacting database");
-            if let Err(err) = dossier.compact().await {
-                eprintln!("Error compacting database: {err}");
+            tokio::time::sleep(CHECK_INTERVAL).await;
+            if let Err(err) = run_if_due(&schedule, &mut last_checked, &dossier).await {
+                eprintln!("Error checking compaction schedule: {err}");
             }
         }
     });
+    Ok(())
+}
+
+async fn run_if_due(
+    schedule: &Schedule,
+    last_checked: &mut Option<u64>,
+    dossier: &ServerDatabase<CliBackend>,
+) -> anyhow::Result<()> {
+    let now = unix_timestamp();
+    let last = last_compaction(dossier).await?;
+    let last_started = last.as_ref().map(|last| last.started_at).max(*last_checked);
+    if !schedule.is_due(last_started, now) {
+        return Ok(());
+    }
+
+    if let Some(last) = &last {
+        let changes = changes_since(last, dossier).await?;
+        if changes < schedule.min_changes {
+            println!("Skipping compaction, {changes} files changed since the last compaction");
+            *last_checked = Some(now);
+            return Ok(());
+        }
+    }
+
+    // Removing garbage first allows compaction to reclaim its space.
+    let collected = gc::collect(dossier, MAX_IDLE.as_secs(), false).await?;
+    println!(
+        "Removed {} expired uploads and {} orphaned files ({})",
+        collected.expired_uploads,
+        collected.orphaned_files,
+        format_bytes(collected.bytes)
+    );
+
+    println!("Compacting database");
+    if let Err(err) = compact(dossier).await {
+        eprintln!("Error compacting database: {err}");
+    }
+    Ok(())
+}
+
+/// Compacts `database`, recording how long it took.
+pub(crate) async fn compact<C: AsyncConnection + Clone + Unpin + 'static>(
+    database: &C,
+) -> anyhow::Result<()> {
+    let started_at = unix_timestamp();
+    let file_count = DossierFiles::stats_for_path_async("/", database)
+        .await?
+        .file_count;
+    let start = Instant::now();
+    let result = database.compact().await;
+    let duration = start.elapsed();
+    metrics()
+        .compaction_duration
+        .observe(duration.as_secs_f64());
+    if result.is_err() {
+        metrics().compaction_failures.inc();
+    }
+
+    Compaction {
+        started_at,
+        duration_ms: u64::try_from(duration.as_millis()).unwrap_or(u64::MAX),
+        error: result.as_ref().err().map(ToString::to_string),
+        file_count,
+    }
+    .push_into_async(database)
+    .await?;
+    Ok(result?)
+}
+
+/// Returns the most recent compaction.
+pub(crate) async fn last_compaction<C: AsyncConnection>(
+    database: &C,
+) -> Result<Option<Compaction>, bonsaidb::core::Error> {
+    Ok(Compaction::all_async(database)
+        .await?
+        .into_iter()
+        .map(|compaction| compaction.contents)
+        .max_by_key(|compaction| compaction.started_at))
+}
+
+/// Returns the number of files written or deleted since `last` started.
+async fn changes_since<C: AsyncConnection + Clone + Unpin + 'static>(
+    last: &Compaction,
+    database: &C,
+) -> anyhow::Result<usize> {
+    let files = DossierFiles::list_recursive_async("/", database).await?;
+    let written = files
+        .iter()
+        .filter(|file| {
+            file.metadata()
+                .is_some_and(|metadata| metadata.written_at >= last.started_at)
+        })
+        .count();
+    // Deletions aren't recorded, so they're estimated from the change in the
+    // number of files.
+    Ok(written + files.len().abs_diff(last.file_count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: u64 = 60 * 60;
+
+    #[test]
+    fn windows() {
+        let night = "22:30-02:00".parse::<Window>().unwrap();
+        assert_eq!(
+            night,
+            Window {
+                start: 22 * 60 + 30,
+                end: 2 * 60
+            }
+        );
+        assert!(night.contains(23 * HOUR));
+        assert!(night.contains(24 * HOUR + HOUR));
+        assert!(!night.contains(2 * HOUR));
+        assert!(!night.contains(22 * HOUR));
+
+        let morning = "03:00-05:00".parse::<Window>().unwrap();
+        assert!(morning.contains(3 * HOUR));
+        assert!(!morning.contains(5 * HOUR));
+
+        assert!("3-5".parse::<Window>().is_err());
+        assert!("24:00-01:00".parse::<Window>().is_err());
+    }
+
+    #[test]
+    fn schedules() {
+        let schedule = Schedule {
+            interval: Duration::from_secs(24 * HOUR),
+            window: Some("02:00-04:00".parse().unwrap()),
+            min_changes: 1,
+        };
+        let day = 10 * 24 * HOUR;
+        assert!(schedule.is_due(None, day + 3 * HOUR));
+        assert!(!schedule.is_due(None, day + 5 * HOUR));
+        // A compaction late in yesterday's window doesn't delay today's.
+        let yesterday = day - 24 * HOUR + 3 * HOUR + 59 * 60;
+        assert!(schedule.is_due(Some(yesterday), day + 2 * HOUR));
+        assert!(!schedule.is_due(Some(day + 2 * HOUR), day + 3 * HOUR));
+
+        let hourly = Schedule {
+            interval: Duration::from_secs(HOUR),
+            window: None,
+            min_changes: 1,
+        };
+        assert!(!hourly.is_due(Some(day), day + HOUR - 1));
+        assert!(hourly.is_due(Some(day), day + HOUR));
+    }
 }
@@ -0,0 +1,156 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt::Display,
+};
+
+use bonsaidb::{
+    core::{connection::AsyncConnection, schema::SerializedCollection},
+    files::FileConfig,
+};
+
+use crate::{
+    api::{compute_hash, staged_at, unix_timestamp, UPLOADS_FOLDER},
+    gc,
+    schema::{DossierFiles, Metadata, Project, UploadSession},
+    uploads::MAX_IDLE,
+};
+
+/// A problem found by [`check`].
+#[derive(Debug)]
+pub(crate) struct Finding {
+    pub path: String,
+    pub problem: Problem,
+    pub repaired: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Problem {
+    /// The file's contents don't match the hash in its metadata.
+    HashMismatch,
+    /// The file has no metadata, which hides it from syncs.
+    MissingMetadata,
+    /// A file in the uploads folder that no upload session refers to.
+    OrphanedUpload,
+    /// The file isn't inside of any project's folder.
+    NoProject,
+    /// Blocks of file contents remain for a file that no longer exists.
+    OrphanedBlocks,
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Problem::HashMismatch => "contents do not match the stored hash",
+            Problem::MissingMetadata => "missing metadata",
+            Problem::OrphanedUpload => "upload without a session",
+            Problem::NoProject => "no project exists for this path",
+            Problem::OrphanedBlocks => "blocks remain after the file was deleted",
+        })
+    }
+}
+
+/// Verifies every file's contents against its stored hash, and looks for files
+/// that can't be reached.
+///
+/// When `repair` is true, hashes are computed for files with missing metadata,
+/// and orphaned uploads and blocks are deleted. Files in the uploads folder are only
+/// considered orphaned once they are older than [`MAX_IDLE`], since an upload's
+/// file is created before its session, and staged files whose age is unknown
+/// are only reported.
+///
+/// Files whose contents don't match their hash are only reported, since the
+/// stored hash is the only record of what the contents should be. Files
+/// without a project are also only reported, since they can be recovered by
+/// recreating the project.
+pub(crate) async fn check<C: AsyncConnection + Clone + Unpin + 'static>(
+    database: &C,
+    repair: bool,
+) -> anyhow::Result<Vec<Finding>> {
+    let projects = Project::all_async(database)
+        .await?
+        .into_iter()
+        .map(|project| project.contents.slug)
+        .collect::<HashSet<_>>();
+    let sessions = UploadSession::all_async(database)
+        .await?
+        .into_iter()
+        .map(|upload| upload.contents.file_id)
+        .collect::<HashSet<_>>();
+
+    let cutoff = unix_timestamp().saturating_sub(MAX_IDLE.as_secs());
+    let mut findings = Vec::new();
+    for mut file in DossierFiles::list_recursive_async("/", database).await? {
+        let path = file.path();
+        if path.starts_with(UPLOADS_FOLDER) {
+            if sessions.contains(&file.id()) {
+                continue;
+            }
+            let repaired = match staged_at(&file.name()) {
+                // The upload may still be starting.
+                Some(created) if created >= cutoff => continue,
+                Some(_) => repair,
+                None => false,
+            };
+            if repaired {
+                DossierFiles::delete_async(&path, database).await?;
+            }
+            findings.push(Finding {
+                path,
+                problem: Problem::OrphanedUpload,
+                repaired,
+            });
+            continue;
+        }
+
+        let slug = path.split('/').nth(1).unwrap_or_default();
+        if !projects.contains(slug) {
+            findings.push(Finding {
+                path: path.clone(),
+                problem: Problem::NoProject,
+                repaired: false,
+            });
+        }
+
+        let hash = compute_hash(&mut file).await?;
+        match file.metadata() {
+            Some(metadata) if metadata.blake3 == hash => {}
+            Some(_) => findings.push(Finding {
+                path,
+                problem: Problem::HashMismatch,
+                repaired: false,
+            }),
+            None => {
+                if repair {
+                    // When the file was written isn't known.
+                    *file.metadata_mut() = Some(Metadata {
+                        blake3: hash,
+                        written_at: 0,
+                    });
+                    file.update_metadata().await?;
+                }
+                findings.push(Finding {
+                    path,
+                    problem: Problem::MissingMetadata,
+                    repaired: repair,
+                });
+            }
+        }
+    }
+
+    let mut blocks = BTreeMap::<u32, Vec<gc::OrphanedBlock>>::new();
+    for block in gc::orphaned_blocks(database).await? {
+        blocks.entry(block.file_id).or_default().push(block);
+    }
+    for (file_id, blocks) in blocks {
+        if repair {
+            gc::delete_blocks(&blocks, database).await?;
+        }
+        findings.push(Finding {
+            path: format!("deleted file #{file_id} ({} blocks)", blocks.len()),
+            problem: Problem::OrphanedBlocks,
+            repaired: repair,
+        });
+    }
+
+    Ok(findings)
+}
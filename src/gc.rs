@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+
+use bonsaidb::{
+    core::{
+        connection::{AsyncConnection, AsyncLowLevelConnection, Range, Sort},
+        document::{DocumentId, Header},
+        schema::SerializedCollection,
+        transaction::{Operation, Transaction},
+    },
+    files::FileConfig,
+};
+
+use crate::{
+    api::{staged_at, unix_timestamp, UPLOADS_FOLDER},
+    schema::{DossierFiles, UploadSession},
+};
+
+/// The result of [`collect`].
+#[derive(Debug, Default)]
+pub(crate) struct Collected {
+    /// Upload sessions that weren't written to within the timeout.
+    pub expired_uploads: usize,
+    /// Staged files that no upload session refers to.
+    pub orphaned_files: usize,
+    /// The total length of the files removed.
+    pub bytes: u64,
+}
+
+/// Removes uploads that haven't been written to in `max_idle_secs`, along with
+/// staged files older than that which no upload session refers to. When
+/// `dry_run` is true, nothing is removed.
+///
+/// Blocks whose file no longer exists are removed separately by
+/// [`collect_blocks`], since finding them reads every block. Folders are
+/// implied by the paths of the files within them, so there are no empty folders
+/// to remove.
+pub(crate) async fn collect<C: AsyncConnection + Clone + Unpin + 'static>(
+    database: &C,
+    max_idle_secs: u64,
+    dry_run: bool,
+) -> anyhow::Result<Collected> {
+    let cutoff = unix_timestamp().saturating_sub(max_idle_secs);
+    let mut collected = Collected::default();
+    let mut staged = DossierFiles::list_recursive_async(UPLOADS_FOLDER, database)
+        .await?
+        .into_iter()
+        .map(|file| (file.id(), file))
+        .collect::<HashMap<_, _>>();
+
+    for upload in UploadSession::all_async(database).await? {
+        let file = staged.remove(&upload.contents.file_id);
+        if upload.contents.last_activity >= cutoff {
+            continue;
+        }
+
+        collected.expired_uploads += 1;
+        if let Some(mut file) = file {
+            collected.bytes += file.len().await?;
+            if !dry_run {
+                DossierFiles::delete_async(&file.path(), database).await?;
+            }
+        }
+        if !dry_run {
+            upload.delete_async(database).await?;
+        }
+    }
+
+    // Files are staged immediately before their session is created, so only
+    // files old enough to have expired are considered orphaned.
+    for mut file in staged.into_values() {
+        if staged_at(&file.name()).is_some_and(|created| created < cutoff) {
+            collected.orphaned_files += 1;
+            collected.bytes += file.len().await?;
+            if !dry_run {
+                DossierFiles::delete_async(&file.path(), database).await?;
+            }
+        }
+    }
+
+    Ok(collected)
+}
+
+/// The number of blocks read or deleted at a time.
+const BLOCK_BATCH: u32 = 256;
+/// The length of the data bonsaidb-files appends to each block's contents: when
+/// the block was written, as 8 bytes, followed by its file's id, as a
+/// big-endian `u32`.
+const BLOCK_APPENDIX_LEN: usize = 12;
+
+/// A block of file contents whose file no longer exists.
+#[derive(Debug)]
+pub(crate) struct OrphanedBlock {
+    pub file_id: u32,
+    pub len: u64,
+    header: Header,
+}
+
+/// Finds the blocks of file contents whose file no longer exists. A file's
+/// blocks are deleted separately from the file, so blocks are left behind if
+/// the deletion is interrupted.
+///
+/// bonsaidb-files doesn't expose its blocks, so they are read as documents and
+/// their file's id is read from the end of their contents. Rather than treating
+/// every block as orphaned if that format changes, this fails unless the
+/// blocks account for the exact length of at least one existing file.
+pub(crate) async fn orphaned_blocks<C: AsyncConnection + Clone + Unpin + 'static>(
+    database: &C,
+) -> anyhow::Result<Vec<OrphanedBlock>> {
+    // Blocks are read before files are listed. Blocks can only be written to
+    // an existing file, so a block's file is in the listing unless it has been
+    // deleted since.
+    let mut blocks = Vec::new();
+    let mut next = 0;
+    loop {
+        let batch = database
+            .list_from_collection(
+                Range::from(DocumentId::from_u64(next)..),
+                Sort::Ascending,
+                Some(BLOCK_BATCH),
+                &DossierFiles::blocks_name(),
+            )
+            .await?;
+        if batch.is_empty() {
+            break;
+        }
+        for block in batch {
+            let id = block.header.id.deserialize::<u64>()?;
+            let Some(len) = block.contents.len().checked_sub(BLOCK_APPENDIX_LEN) else {
+                anyhow::bail!("block {id} is too short to be a block");
+            };
+            let file_id = u32::from_be_bytes(block.contents[len + 8..].try_into()?);
+            next = id + 1;
+            blocks.push(OrphanedBlock {
+                file_id,
+                len: len as u64,
+                header: block.header,
+            });
+        }
+    }
+
+    let mut files = DossierFiles::list_recursive_async("/", database)
+        .await?
+        .into_iter()
+        .map(|file| (file.id(), file))
+        .collect::<HashMap<_, _>>();
+    let mut stored = HashMap::<u32, u64>::new();
+    for block in &blocks {
+        *stored.entry(block.file_id).or_default() += block.len;
+    }
+    let mut recognized = blocks.is_empty();
+    for (file_id, len) in stored {
+        if let Some(file) = files.get_mut(&file_id) {
+            if file.len().await? == len {
+                recognized = true;
+                break;
+            }
+        }
+    }
+    if !recognized {
+        anyhow::bail!(
+            "no blocks matched an existing file, so they may be stored in a format this version \
+             doesn't recognize"
+        );
+    }
+
+    blocks.retain(|block| !files.contains_key(&block.file_id));
+    Ok(blocks)
+}
+
+/// Deletes `blocks`, which were found by [`orphaned_blocks`].
+pub(crate) async fn delete_blocks<C: AsyncConnection + Clone + Unpin + 'static>(
+    blocks: &[OrphanedBlock],
+    database: &C,
+) -> anyhow::Result<()> {
+    for batch in blocks.chunks(BLOCK_BATCH as usize) {
+        let mut transaction = Transaction::new();
+        for block in batch {
+            transaction.push(Operation::delete(
+                DossierFiles::blocks_name(),
+                block.header.clone(),
+            ));
+        }
+        database.apply_transaction(transaction).await?;
+    }
+    Ok(())
+}
+
+/// The result of [`collect_blocks`].
+#[derive(Debug, Default)]
+pub(crate) struct CollectedBlocks {
+    pub blocks: usize,
+    pub bytes: u64,
+}
+
+/// Removes every block whose file no longer exists. When `dry_run` is true,
+/// nothing is removed.
+pub(crate) async fn collect_blocks<C: AsyncConnection + Clone + Unpin + 'static>(
+    database: &C,
+    dry_run: bool,
+) -> anyhow::Result<CollectedBlocks> {
+    let blocks = orphaned_blocks(database).await?;
+    if !dry_run {
+        delete_blocks(&blocks, database).await?;
+    }
+    Ok(CollectedBlocks {
+        blocks: blocks.len(),
+        bytes: blocks.iter().map(|block| block.len).sum(),
+    })
+}
@@ -1,10 +1,21 @@
 #![doc = include_str!("../README.md")]
 
+mod access_log;
 mod api;
+mod archive;
 mod cli;
 mod compactor;
+mod fsck;
+mod gc;
+mod metrics;
 mod permissions;
+mod progress;
+mod retention;
 mod schema;
+mod status;
+mod sync_filter;
+mod uploads;
+mod usage;
 mod webserver;
 
 use std::{convert::Infallible, num::NonZeroUsize};
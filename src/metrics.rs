@@ -0,0 +1,180 @@
+use std::{collections::HashSet, sync::OnceLock, time::Duration};
+
+use bonsaidb::{
+    core::{connection::AsyncConnection, schema::SerializedCollection},
+    files::FileConfig,
+    server::ServerDatabase,
+};
+use parking_lot::RwLock;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+
+use crate::{
+    schema::{DossierFiles, Project},
+    CliBackend,
+};
+
+/// The label used for requests that don't belong to a known project.
+const NO_PROJECT: &str = "-";
+
+/// How often the per-project storage statistics are recomputed. Computing them
+/// reads every project's files, so it isn't done for each scrape.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Metrics exposed in the Prometheus text format at `/_metrics`.
+pub(crate) struct Metrics {
+    registry: Registry,
+    pub http_requests: IntCounterVec,
+    pub http_request_duration: HistogramVec,
+    pub http_response_bytes: IntCounterVec,
+    pub file_lookup_duration: Histogram,
+    pub api_requests: IntCounterVec,
+    pub project_storage_bytes: IntGaugeVec,
+    pub project_files: IntGaugeVec,
+    pub compaction_duration: Histogram,
+    pub compaction_failures: IntCounter,
+    /// The slugs of every project, refreshed each time metrics are rendered.
+    /// Requests are only labeled with a project found in this set to keep
+    /// arbitrary request paths from creating new time series.
+    known_projects: RwLock<HashSet<String>>,
+}
+
+pub(crate) fn launch(dossier: ServerDatabase<CliBackend>) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(REFRESH_INTERVAL).await;
+            if let Err(err) = metrics().refresh_projects(&dossier).await {
+                eprintln!("Error refreshing project metrics: {err}");
+            }
+        }
+    });
+}
+
+pub(crate) fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(|| Metrics::new().expect("invalid metric definitions"))
+}
+
+impl Metrics {
+    fn new() -> prometheus::Result<Self> {
+        let registry = Registry::new_custom(Some(String::from("dossier")), None)?;
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests handled"),
+            &["project", "status"],
+        )?;
+        registry.register(Box::new(http_requests.clone()))?;
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time taken to produce the response headers of HTTP requests",
+            ),
+            &["project", "status"],
+        )?;
+        registry.register(Box::new(http_request_duration.clone()))?;
+        let http_response_bytes = IntCounterVec::new(
+            Opts::new("http_response_bytes_total", "Response body bytes sent"),
+            &["project"],
+        )?;
+        registry.register(Box::new(http_response_bytes.clone()))?;
+        let file_lookup_duration = Histogram::with_opts(HistogramOpts::new(
+            "file_lookup_duration_seconds",
+            "Time taken to find the file for a request in the database",
+        ))?;
+        registry.register(Box::new(file_lookup_duration.clone()))?;
+        let api_requests = IntCounterVec::new(
+            Opts::new("api_requests_total", "API requests handled"),
+            &["request", "outcome"],
+        )?;
+        registry.register(Box::new(api_requests.clone()))?;
+        let project_storage_bytes = IntGaugeVec::new(
+            Opts::new("project_storage_bytes", "Total size of a project's files"),
+            &["project"],
+        )?;
+        registry.register(Box::new(project_storage_bytes.clone()))?;
+        let project_files = IntGaugeVec::new(
+            Opts::new("project_files", "Number of files in a project"),
+            &["project"],
+        )?;
+        registry.register(Box::new(project_files.clone()))?;
+        let compaction_duration = Histogram::with_opts(
+            HistogramOpts::new(
+                "compaction_duration_seconds",
+                "Time taken to compact the database",
+            )
+            .buckets(vec![1., 5., 15., 30., 60., 120., 300., 600., 1800., 3600.]),
+        )?;
+        registry.register(Box::new(compaction_duration.clone()))?;
+        let compaction_failures = IntCounter::with_opts(Opts::new(
+            "compaction_failures_total",
+            "Compactions that returned an error",
+        ))?;
+        registry.register(Box::new(compaction_failures.clone()))?;
+
+        Ok(Self {
+            registry,
+            http_requests,
+            http_request_duration,
+            http_response_bytes,
+            file_lookup_duration,
+            api_requests,
+            project_storage_bytes,
+            project_files,
+            compaction_duration,
+            compaction_failures,
+            known_projects: RwLock::default(),
+        })
+    }
+
+    /// Returns the project label for a request for `path`.
+    pub fn project_label(&self, path: &str) -> String {
+        let slug = path.split('/').nth(1).unwrap_or_default();
+        if self.known_projects.read().contains(slug) {
+            slug.to_string()
+        } else {
+            String::from(NO_PROJECT)
+        }
+    }
+
+    /// Records an API request named `request`, returning `result` unchanged.
+    pub fn record_api_request<T, E>(&self, request: &str, result: Result<T, E>) -> Result<T, E> {
+        let outcome = if result.is_ok() { "ok" } else { "error" };
+        self.api_requests
+            .with_label_values(&[request, outcome])
+            .inc();
+        result
+    }
+
+    /// Renders all metrics. The per-project storage statistics are as of the
+    /// last [`refresh_projects`](Self::refresh_projects).
+    pub fn render(&self) -> anyhow::Result<String> {
+        let mut rendered = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut rendered)?;
+        Ok(String::from_utf8(rendered)?)
+    }
+
+    /// Reloads the list of projects and their storage statistics.
+    pub async fn refresh_projects<C: AsyncConnection + Clone>(
+        &self,
+        database: &C,
+    ) -> anyhow::Result<()> {
+        let projects = Project::all_async(database).await?;
+        self.project_storage_bytes.reset();
+        self.project_files.reset();
+        let mut known_projects = HashSet::with_capacity(projects.len());
+        for project in projects {
+            let slug = project.contents.slug;
+            let stats = DossierFiles::stats_for_path_async(&format!("/{slug}/"), database).await?;
+            self.project_storage_bytes
+                .with_label_values(&[&slug])
+                .set(i64::try_from(stats.total_bytes).unwrap_or(i64::MAX));
+            self.project_files
+                .with_label_values(&[&slug])
+                .set(i64::try_from(stats.file_count).unwrap_or(i64::MAX));
+            known_projects.insert(slug);
+        }
+        *self.known_projects.write() = known_projects;
+        Ok(())
+    }
+}
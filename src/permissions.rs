@@ -26,8 +26,14 @@ pub fn project_resource_name(project_id: u32) -> ResourceName<'static> {
         .and(u64::from(project_id))
 }
 
+/// The resource checked before reporting the server's status.
+pub fn status_resource_name() -> ResourceName<'static> {
+    ResourceName::named("dossier").and("status")
+}
+
 #[derive(Action, Debug)]
 #[action(actionable = bonsaidb::core::actionable)]
 pub enum DossierAction {
     SyncFiles,
+    ViewStatus,
 }
@@ -0,0 +1,230 @@
+use std::{
+    fmt::Display,
+    io::{IsTerminal, Write},
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+
+use crate::cli::OutputFormat;
+
+/// How often the progress line is redrawn.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Reports the progress of uploads during a sync.
+pub(crate) struct Progress {
+    style: ProgressStyle,
+    state: Mutex<ProgressState>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ProgressStyle {
+    /// Nothing is printed other than warnings.
+    Quiet,
+    /// A line is printed for each completed file.
+    Lines(OutputFormat),
+    /// A single status line is redrawn on stderr.
+    Bar,
+}
+
+struct ProgressState {
+    total: usize,
+    completed: usize,
+    bytes_sent: u64,
+    started: Instant,
+    last_drawn: Option<Instant>,
+}
+
+impl Progress {
+    /// Returns a progress reporter for `total` operations. A redrawn status
+    /// line is used when stderr is a terminal.
+    pub fn new(total: usize, quiet: bool, format: OutputFormat) -> Self {
+        let style = if quiet {
+            ProgressStyle::Quiet
+        } else if std::io::stderr().is_terminal() {
+            ProgressStyle::Bar
+        } else {
+            ProgressStyle::Lines(format)
+        };
+        Self {
+            style,
+            state: Mutex::new(ProgressState {
+                total,
+                completed: 0,
+                bytes_sent: 0,
+                started: Instant::now(),
+                last_drawn: None,
+            }),
+        }
+    }
+
+    /// Records that the file at `path` was uploaded.
+    pub fn file_completed(&self, path: &str, bytes: u64) {
+        let mut state = self.state.lock();
+        state.completed += 1;
+        state.bytes_sent += bytes;
+        match self.style {
+            ProgressStyle::Quiet => {}
+            ProgressStyle::Lines(format) => {
+                format.status(format_args!("{path} ({}/{})", state.completed, state.total));
+            }
+            ProgressStyle::Bar => {
+                let now = Instant::now();
+                let redraw = match state.last_drawn {
+                    Some(drawn) => now - drawn >= REDRAW_INTERVAL,
+                    None => true,
+                };
+                if redraw || state.completed == state.total {
+                    state.last_drawn = Some(now);
+                    draw(&state);
+                }
+            }
+        }
+    }
+
+    /// Prints a warning without corrupting the status line.
+    pub fn warn(&self, message: impl Display) {
+        let state = self.state.lock();
+        if let ProgressStyle::Bar = self.style {
+            eprint!("\r\x1b[2K");
+            eprintln!("{message}");
+            if state.last_drawn.is_some() {
+                draw(&state);
+            }
+        } else {
+            eprintln!("{message}");
+        }
+    }
+
+    /// Clears the status line, returning the number of bytes uploaded.
+    pub fn finish(&self) -> u64 {
+        let state = self.state.lock();
+        if let ProgressStyle::Bar = self.style {
+            if state.last_drawn.is_some() {
+                eprint!("\r\x1b[2K");
+                let _ = std::io::stderr().flush();
+            }
+        }
+        state.bytes_sent
+    }
+}
+
+fn draw(state: &ProgressState) {
+    let elapsed = state.started.elapsed();
+    eprint!(
+        "\r\x1b[2K[{}/{}] {} sent, {}",
+        state.completed,
+        state.total,
+        format_bytes(state.bytes_sent),
+        format_throughput(state.bytes_sent, elapsed)
+    );
+    let _ = std::io::stderr().flush();
+}
+
+/// The outcome of a sync, printed once it completes.
+#[derive(Debug, Default)]
+pub(crate) struct SyncSummary {
+    pub created: usize,
+    pub replaced: usize,
+    pub deleted: usize,
+    /// Local files that matched the remote file and weren't uploaded.
+    pub skipped: usize,
+    pub failed: usize,
+    pub bytes_sent: u64,
+    pub upload_duration: Duration,
+    pub elapsed: Duration,
+}
+
+impl Display for SyncSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} created, {} replaced, {} deleted, {} unchanged",
+            self.created, self.replaced, self.deleted, self.skipped
+        )?;
+        if self.failed > 0 {
+            write!(f, ", {} failed", self.failed)?;
+        }
+        write!(
+            f,
+            ". Sent {} in {:.1}s ({}), {:.1}s total.",
+            format_bytes(self.bytes_sent),
+            self.upload_duration.as_secs_f32(),
+            format_throughput(self.bytes_sent, self.upload_duration),
+            self.elapsed.as_secs_f32()
+        )
+    }
+}
+
+/// Formats `bytes` using binary units, e.g. `1.5 MiB`.
+pub(crate) fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+
+    let mut value = bytes as f64 / 1024.;
+    let mut unit = 0;
+    while value >= 1024. && unit < UNITS.len() - 1 {
+        value /= 1024.;
+        unit += 1;
+    }
+    format!("{value:.1} {}", UNITS[unit])
+}
+
+/// Formats `duration` using its two largest units, e.g. `3h 12m`.
+pub(crate) fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match seconds {
+        0..=59 => format!("{seconds}s"),
+        60..=3599 => format!("{}m {}s", seconds / 60, seconds % 60),
+        3600..=86399 => format!("{}h {}m", seconds / 3600, seconds % 3600 / 60),
+        _ => format!("{}d {}h", seconds / 86400, seconds % 86400 / 3600),
+    }
+}
+
+fn format_throughput(bytes: u64, elapsed: Duration) -> String {
+    let seconds = elapsed.as_secs_f64();
+    let per_second = if seconds > 0. {
+        (bytes as f64 / seconds) as u64
+    } else {
+        0
+    };
+    format!("{}/s", format_bytes(per_second))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bytes() {
+        assert_eq!(format_bytes(0), "0 B");
+        assert_eq!(format_bytes(1023), "1023 B");
+        assert_eq!(format_bytes(1024), "1.0 KiB");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(1024 * 1024), "1.0 MiB");
+        assert_eq!(format_bytes(5 * 1024 * 1024 * 1024), "5.0 GiB");
+        assert_eq!(format_bytes(1 << 50), "1.0 PiB");
+        // Units stop at PiB.
+        assert_eq!(format_bytes(1 << 60), "1024.0 PiB");
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(format_duration(Duration::from_secs(0)), "0s");
+        assert_eq!(format_duration(Duration::from_millis(59_999)), "59s");
+        assert_eq!(format_duration(Duration::from_secs(60)), "1m 0s");
+        assert_eq!(format_duration(Duration::from_secs(3599)), "59m 59s");
+        assert_eq!(format_duration(Duration::from_secs(3600)), "1h 0m");
+        assert_eq!(
+            format_duration(Duration::from_secs(3 * 3600 + 12 * 60 + 5)),
+            "3h 12m"
+        );
+        assert_eq!(format_duration(Duration::from_secs(86400)), "1d 0h");
+        assert_eq!(
+            format_duration(Duration::from_secs(2 * 86400 + 5 * 3600)),
+            "2d 5h"
+        );
+    }
+}
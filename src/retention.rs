@@ -0,0 +1,279 @@
+use std::{collections::HashMap, time::Duration};
+
+use bonsaidb::{
+    core::{connection::AsyncConnection, schema::SerializedCollection},
+    files::FileConfig,
+    server::ServerDatabase,
+};
+
+use crate::{
+    api::unix_timestamp,
+    schema::{Alias, DossierFiles, Project, RetentionPolicy, RetentionRule},
+    CliBackend,
+};
+
+pub(crate) fn launch(dossier: ServerDatabase<CliBackend>) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_secs(60 * 60)).await;
+            if let Err(err) = enforce(&dossier, false).await {
+                eprintln!("Error enforcing retention rules: {err}");
+            }
+        }
+    });
+}
+
+/// A folder removed, or that would be removed, by a retention rule.
+#[derive(Debug)]
+pub(crate) struct Removal {
+    pub folder: String,
+    pub files: usize,
+    pub rule: u64,
+    pub reason: String,
+}
+
+/// Applies every retention rule, returning the folders that were removed. When
+/// `dry_run` is true, nothing is deleted.
+///
+/// Folders that an alias points to, or that contain files without a known
+/// write time, are never removed.
+pub(crate) async fn enforce<C: AsyncConnection + Clone + Unpin + 'static>(
+    database: &C,
+    dry_run: bool,
+) -> anyhow::Result<Vec<Removal>> {
+    let aliases = Alias::all_async(database).await?;
+    let now = unix_timestamp();
+    let mut removals = Vec::new();
+    for rule in RetentionRule::all_async(database).await? {
+        let Some(project) = Project::get_async(&rule.contents.project_id, database).await? else {
+            continue;
+        };
+        let root = format!("/{}", project.contents.slug);
+
+        // Group the project's files by the folder matching the rule.
+        let mut folders = HashMap::<String, FolderStats>::new();
+        for file in DossierFiles::list_recursive_async(&format!("{root}/"), database).await? {
+            let path = file.path();
+            let Some(folder) = matching_folder(&rule.contents.pattern, &path[root.len()..]) else {
+                continue;
+            };
+            let stats = folders.entry(format!("{root}{folder}")).or_default();
+            stats.files.push(path.clone());
+            match file.metadata() {
+                Some(metadata) if metadata.written_at > 0 => {
+                    stats.newest = stats.newest.max(metadata.written_at);
+                }
+                _ => stats.unknown_age = true,
+            }
+        }
+        folders.retain(|folder, stats| {
+            !stats.unknown_age
+                && !aliases
+                    .iter()
+                    .any(|alias| paths_overlap(folder, &alias.contents.target))
+        });
+
+        let ages = folders
+            .iter()
+            .map(|(folder, stats)| (folder.as_str(), stats.newest))
+            .collect::<Vec<_>>();
+        let expired = expired_folders(rule.contents.policy, &ages, now)
+            .into_iter()
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+        for folder in expired {
+            let stats = &folders[&folder];
+            if !dry_run {
+                for path in &stats.files {
+                    DossierFiles::delete_async(path, database).await?;
+                }
+                println!(
+                    "Retention rule {} removed {folder} ({} files)",
+                    rule.header.id,
+                    stats.files.len()
+                );
+            }
+            removals.push(Removal {
+                files: stats.files.len(),
+                rule: rule.header.id,
+                reason: describe_policy(rule.contents.policy),
+                folder,
+            });
+        }
+    }
+
+    Ok(removals)
+}
+
+#[derive(Default)]
+struct FolderStats {
+    files: Vec<String>,
+    newest: u64,
+    unknown_age: bool,
+}
+
+pub(crate) fn describe_policy(policy: RetentionPolicy) -> String {
+    match policy {
+        RetentionPolicy::MaxAgeDays(days) => format!("not written in {days} days"),
+        RetentionPolicy::KeepNewest(count) => format!("not one of the {count} newest"),
+    }
+}
+
+/// Returns the folders from `folders`, which are paired with the time they
+/// were last written to, that `policy` removes.
+fn expired_folders<'a>(
+    policy: RetentionPolicy,
+    folders: &[(&'a str, u64)],
+    now: u64,
+) -> Vec<&'a str> {
+    match policy {
+        RetentionPolicy::MaxAgeDays(days) => {
+            let cutoff = now.saturating_sub(u64::from(days) * 24 * 60 * 60);
+            folders
+                .iter()
+                .filter(|(_, written_at)| *written_at < cutoff)
+                .map(|(folder, _)| *folder)
+                .collect()
+        }
+        RetentionPolicy::KeepNewest(count) => {
+            let mut folders = folders.to_vec();
+            // Newest first, using the path to keep the order stable.
+            folders.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+            folders
+                .into_iter()
+                .skip(count)
+                .map(|(folder, _)| folder)
+                .collect()
+        }
+    }
+}
+
+/// Returns true if one of the folders `a` and `b` contains the other. Paths are
+/// compared by whole segments, so `/project/pr-1` doesn't contain
+/// `/project/pr-12/`.
+fn paths_overlap(a: &str, b: &str) -> bool {
+    let folder = |path: &str| {
+        if path.ends_with('/') {
+            path.to_string()
+        } else {
+            format!("{path}/")
+        }
+    };
+    let (a, b) = (folder(a), folder(b));
+    a.starts_with(&b) || b.starts_with(&a)
+}
+
+/// Returns the folder matching `pattern` that contains `path`. Both are
+/// relative to a project's root and begin with a `/`.
+fn matching_folder<'a>(pattern: &str, path: &'a str) -> Option<&'a str> {
+    let mut folder_len = 0;
+    let mut segments = path.split_terminator('/').skip(1);
+    for pattern_segment in pattern.split_terminator('/').skip(1) {
+        let segment = segments.next()?;
+        if !glob_matches(pattern_segment, segment) {
+            return None;
+        }
+        folder_len += segment.len() + 1;
+    }
+
+    // Only files within the folder are matched, not the folder itself.
+    segments.next()?;
+    Some(&path[..=folder_len])
+}
+
+/// Matches `name` against `pattern`, where `*` matches any number of
+/// characters and `?` matches a single character.
+fn glob_matches(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let name = name.chars().collect::<Vec<_>>();
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some('?') => {
+                p += 1;
+                n += 1;
+            }
+            Some(ch) if *ch == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    n = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|ch| *ch == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn globs() {
+        assert!(glob_matches("pr-*", "pr-12"));
+        assert!(glob_matches("pr-*", "pr-"));
+        assert!(!glob_matches("pr-*", "main"));
+        assert!(glob_matches("*-docs", "feature-docs"));
+        assert!(glob_matches("v?.*", "v0.5"));
+        assert!(!glob_matches("v?", "v10"));
+        assert!(glob_matches("*", "anything"));
+    }
+
+    #[test]
+    fn folders() {
+        assert_eq!(
+            matching_folder("/pr-*/", "/pr-12/index.html"),
+            Some("/pr-12/")
+        );
+        assert_eq!(matching_folder("/pr-*/", "/pr-12/a/b.css"), Some("/pr-12/"));
+        assert_eq!(matching_folder("/pr-*/", "/main/index.html"), None);
+        assert_eq!(matching_folder("/pr-*/", "/pr-12"), None);
+        assert_eq!(
+            matching_folder("/nightly/*/", "/nightly/2023-01-01/index.html"),
+            Some("/nightly/2023-01-01/")
+        );
+        assert_eq!(matching_folder("/nightly/*/", "/nightly/index.html"), None);
+    }
+
+    #[test]
+    fn alias_overlap() {
+        assert!(paths_overlap("/project/pr-1/", "/project/pr-1/"));
+        assert!(paths_overlap("/project/pr-1/", "/project/pr-1"));
+        assert!(paths_overlap("/project/pr-1/", "/project/pr-1/docs/"));
+        assert!(paths_overlap("/project/pr-1/docs/", "/project/"));
+        assert!(!paths_overlap("/project/pr-12/", "/project/pr-1"));
+        assert!(!paths_overlap("/project/pr-12/", "/project/pr-1/"));
+        assert!(!paths_overlap("/project/pr-1/", "/project/pr-12/"));
+    }
+
+    #[test]
+    fn policies() {
+        const DAY: u64 = 24 * 60 * 60;
+        let now = 100 * DAY;
+        let folders = [
+            ("/a/", now - DAY),
+            ("/b/", now - 40 * DAY),
+            ("/c/", now - 10 * DAY),
+        ];
+        assert_eq!(
+            expired_folders(RetentionPolicy::MaxAgeDays(30), &folders, now),
+            ["/b/"]
+        );
+        assert_eq!(
+            expired_folders(RetentionPolicy::KeepNewest(1), &folders, now),
+            ["/c/", "/b/"]
+        );
+        assert!(expired_folders(RetentionPolicy::KeepNewest(5), &folders, now).is_empty());
+    }
+}
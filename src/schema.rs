@@ -13,7 +13,7 @@ use serde::{Deserialize, Serialize};
 use crate::permissions::{project_resource_name, DossierAction};
 
 #[derive(Schema, Debug)]
-#[schema(name = "dossier", collections = [Project, ApiToken], include = [FilesSchema<DossierFiles>])]
+#[schema(name = "dossier", collections = [Project, ApiToken, UploadSession, Alias, RetentionRule, Compaction], include = [FilesSchema<DossierFiles>])]
 pub struct Dossier;
 
 #[derive(Debug)]
@@ -35,12 +35,29 @@ impl FileConfig for DossierFiles {
 #[derive(Debug, Clone, Serialize, Deserialize, Copy)]
 pub struct Metadata {
     pub blake3: [u8; 32],
+    /// When the file's contents were written, in seconds since the Unix
+    /// epoch. Files written before this was tracked have a value of 0.
+    #[serde(default)]
+    pub written_at: u64,
 }
 
 #[derive(Collection, Debug, Clone, Serialize, Deserialize)]
 #[collection(name = "projects", primary_key = u32, views = [ProjectBySlug])]
 pub struct Project {
     pub slug: String,
+    #[serde(default)]
+    pub quota: ProjectQuota,
+}
+
+/// Limits on how much a project may store. Each limit is disabled when `None`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProjectQuota {
+    /// The total size of the project's files, in bytes.
+    pub max_bytes: Option<u64>,
+    /// The number of files in the project.
+    pub max_files: Option<usize>,
+    /// The size of any single file, in bytes.
+    pub max_file_size: Option<u64>,
 }
 
 bonsaidb::core::define_basic_unique_mapped_view!(
@@ -64,6 +81,82 @@ pub struct ApiToken {
     pub project_id: u32,
 }
 
+/// A virtual folder whose contents are served from another folder, e.g.
+/// `/bonsaidb/latest/` serving `/bonsaidb/v0.5/`. Both paths end with a `/`.
+#[derive(Collection, Debug, Clone, Serialize, Deserialize)]
+#[collection(name = "aliases", primary_key = u64, views = [AliasByPath])]
+pub struct Alias {
+    pub path: String,
+    pub target: String,
+    /// When true, requests are redirected to the target rather than being
+    /// served from it.
+    pub redirect: bool,
+}
+
+bonsaidb::core::define_basic_unique_mapped_view!(
+    AliasByPath,
+    Alias,
+    1,
+    "by-path",
+    String,
+    |alias: CollectionDocument<Alias>| alias.header.emit_key(alias.contents.path)
+);
+
+impl NamedCollection for Alias {
+    type ByNameView = AliasByPath;
+}
+
+/// A rule that removes folders from a project once they are no longer needed.
+#[derive(Collection, Debug, Clone, Serialize, Deserialize)]
+#[collection(name = "retention-rules", primary_key = u64)]
+pub struct RetentionRule {
+    pub project_id: u32,
+    /// The folders this rule applies to, relative to the project's root, e.g.
+    /// `/pr-*/` or `/nightly/*/`. Each segment may contain `*` and `?`
+    /// wildcards.
+    pub pattern: String,
+    pub policy: RetentionPolicy,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum RetentionPolicy {
+    /// Removes folders whose newest file was written more than this many days
+    /// ago.
+    MaxAgeDays(u32),
+    /// Keeps only this many of the most recently written folders.
+    KeepNewest(usize),
+}
+
+/// A compaction of the database, recorded so that the compaction schedule
+/// survives restarts.
+#[derive(Collection, Debug, Clone, Serialize, Deserialize)]
+#[collection(name = "compactions", primary_key = u64)]
+pub struct Compaction {
+    /// When the compaction started, in seconds since the Unix epoch.
+    pub started_at: u64,
+    pub duration_ms: u64,
+    /// The error that caused the compaction to fail, if it failed.
+    pub error: Option<String>,
+    /// The number of files when the compaction started.
+    pub file_count: usize,
+}
+
+/// An upload in progress, created by `StartUpload`. Chunks are appended to a
+/// file in the uploads folder, which replaces the file at `path` once the
+/// upload is finished.
+#[derive(Collection, Debug, Clone, Serialize, Deserialize)]
+#[collection(name = "upload-sessions", primary_key = u64)]
+pub struct UploadSession {
+    pub path: String,
+    pub file_id: u32,
+    /// The length of the file being uploaded, which is reserved in the
+    /// project's quota until the upload finishes or expires.
+    #[serde(default)]
+    pub len: u64,
+    /// The time of the last write, in seconds since the Unix epoch.
+    pub last_activity: u64,
+}
+
 impl ApiToken {
     pub async fn create<C: AsyncConnection>(
         label: String,
@@ -0,0 +1,77 @@
+use std::{
+    path::Path,
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::schema::Compaction;
+
+/// The directory the server stores its databases in.
+pub const STORAGE_PATH: &str = "dossier.bonsaidb";
+
+/// Information about the running server, returned by
+/// [`GetServerStatus`](crate::api::GetServerStatus).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ServerStatus {
+    /// The version of dossier the server is running.
+    pub version: String,
+    /// How long the server has been running, in seconds. This is `None` when
+    /// the database was opened locally rather than by a running server.
+    pub uptime_secs: Option<u64>,
+    /// The total size of the server's storage on disk.
+    pub storage_bytes: u64,
+    pub project_count: u64,
+    pub file_count: usize,
+    /// The total length of every file.
+    pub file_bytes: u64,
+    /// The most recent compaction, if one has run.
+    pub last_compaction: Option<Compaction>,
+}
+
+fn started_at() -> &'static Instant {
+    static STARTED_AT: OnceLock<Instant> = OnceLock::new();
+    STARTED_AT.get_or_init(Instant::now)
+}
+
+/// Records the time the server started, which [`uptime`] is measured from.
+pub fn mark_started() {
+    started_at();
+}
+
+pub fn uptime() -> Duration {
+    started_at().elapsed()
+}
+
+/// Returns the total length of the files within `path`.
+pub fn disk_usage(path: &Path) -> std::io::Result<u64> {
+    let metadata = std::fs::symlink_metadata(path)?;
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+
+    let mut total = 0;
+    for entry in std::fs::read_dir(path)? {
+        total += disk_usage(&entry?.path())?;
+    }
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disk_usage_is_recursive() {
+        let root = std::env::temp_dir().join(format!("dossier-disk-usage-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("a/b")).unwrap();
+        std::fs::write(root.join("one"), [0; 10]).unwrap();
+        std::fs::write(root.join("a/two"), [0; 20]).unwrap();
+        std::fs::write(root.join("a/b/three"), [0; 30]).unwrap();
+
+        let result = disk_usage(&root);
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(result.unwrap(), 60);
+    }
+}
@@ -0,0 +1,130 @@
+use std::path::Path;
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+
+/// The name of the file in the root of a synchronized directory that lists
+/// patterns to exclude, using gitignore syntax.
+pub const IGNORE_FILE_NAME: &str = ".dossierignore";
+
+/// Decides which files `project sync` manages.
+///
+/// All patterns use gitignore semantics and are matched against paths relative
+/// to the root of the sync. A file is synced if it isn't excluded by
+/// `.dossierignore` or `--exclude`, and if `--include` patterns were provided,
+/// it matches at least one of them.
+///
+/// Remote files that aren't synced are never deleted, which allows multiple
+/// syncs with different filters to share a remote path.
+#[derive(Debug)]
+pub struct SyncFilter {
+    ignore_file: Gitignore,
+    excludes: Gitignore,
+    includes: Option<Gitignore>,
+}
+
+impl SyncFilter {
+    pub fn new(root: &Path, excludes: &[String], includes: &[String]) -> anyhow::Result<Self> {
+        let mut ignore_file = GitignoreBuilder::new(root);
+        let ignore_file_path = root.join(IGNORE_FILE_NAME);
+        if ignore_file_path.exists() {
+            if let Some(err) = ignore_file.add(ignore_file_path) {
+                anyhow::bail!("error reading {IGNORE_FILE_NAME}: {err}");
+            }
+        }
+
+        let includes = if includes.is_empty() {
+            None
+        } else {
+            Some(build_matcher(root, includes)?)
+        };
+
+        Ok(Self {
+            ignore_file: ignore_file.build()?,
+            excludes: build_matcher(root, excludes)?,
+            includes,
+        })
+    }
+
+    /// Returns true if the directory at `relative_path` should not be
+    /// traversed. Its parent directories must have already been checked.
+    pub fn is_excluded_directory(&self, relative_path: &str) -> bool {
+        let relative_path = relative_path.trim_end_matches('/');
+        self.ignore_file.matched(relative_path, true).is_ignore()
+            || self.excludes.matched(relative_path, true).is_ignore()
+    }
+
+    /// Returns true if the file at `relative_path` should be synced.
+    pub fn should_sync(&self, relative_path: &str) -> bool {
+        if relative_path == IGNORE_FILE_NAME
+            || self
+                .ignore_file
+                .matched_path_or_any_parents(relative_path, false)
+                .is_ignore()
+            || self
+                .excludes
+                .matched_path_or_any_parents(relative_path, false)
+                .is_ignore()
+        {
+            return false;
+        }
+
+        match &self.includes {
+            Some(includes) => includes
+                .matched_path_or_any_parents(relative_path, false)
+                .is_ignore(),
+            None => true,
+        }
+    }
+}
+
+fn build_matcher(root: &Path, patterns: &[String]) -> anyhow::Result<Gitignore> {
+    let mut builder = GitignoreBuilder::new(root);
+    for pattern in patterns {
+        builder.add_line(None, pattern)?;
+    }
+    Ok(builder.build()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(excludes: &[&str], includes: &[&str]) -> SyncFilter {
+        let owned = |patterns: &[&str]| patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        SyncFilter::new(
+            Path::new("/nonexistent"),
+            &owned(excludes),
+            &owned(includes),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn excludes() {
+        let filter = filter(&["*.lock", ".DS_Store", "/.crates.toml", "target/"], &[]);
+        assert!(filter.should_sync("index.html"));
+        assert!(filter.should_sync("a/b/index.html"));
+        assert!(!filter.should_sync("a/.lock"));
+        assert!(!filter.should_sync("Cargo.lock"));
+        assert!(!filter.should_sync("a/b/.DS_Store"));
+        assert!(!filter.should_sync(".crates.toml"));
+        assert!(filter.should_sync("a/.crates.toml"));
+        assert!(!filter.should_sync("target/debug/file"));
+        assert!(filter.is_excluded_directory("target"));
+        assert!(filter.is_excluded_directory("a/target/"));
+        assert!(!filter.is_excluded_directory("a"));
+        assert!(!filter.should_sync(IGNORE_FILE_NAME));
+    }
+
+    #[test]
+    fn includes() {
+        let filter = filter(&["private/"], &["*.html", "static/"]);
+        assert!(filter.should_sync("index.html"));
+        assert!(filter.should_sync("a/b.html"));
+        assert!(filter.should_sync("static/a/b.css"));
+        assert!(!filter.should_sync("a/b.css"));
+        assert!(!filter.should_sync("private/index.html"));
+        // Included patterns don't prevent traversing directories.
+        assert!(!filter.is_excluded_directory("a"));
+    }
+}
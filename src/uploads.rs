@@ -0,0 +1,24 @@
+use std::time::Duration;
+
+use bonsaidb::server::ServerDatabase;
+
+use crate::{gc, CliBackend};
+
+/// Upload sessions that haven't received data for this long are removed.
+pub(crate) const MAX_IDLE: Duration = Duration::from_secs(24 * 60 * 60);
+
+pub(crate) fn launch(dossier: ServerDatabase<CliBackend>) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_secs(60 * 60)).await;
+            match gc::collect(&dossier, MAX_IDLE.as_secs(), false).await {
+                Ok(collected) if collected.expired_uploads + collected.orphaned_files == 0 => {}
+                Ok(collected) => println!(
+                    "Removed {} expired uploads and {} orphaned files",
+                    collected.expired_uploads, collected.orphaned_files
+                ),
+                Err(err) => eprintln!("Error expiring upload sessions: {err}"),
+            }
+        }
+    });
+}
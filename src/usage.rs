@@ -0,0 +1,129 @@
+use std::collections::{BTreeMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+/// The storage used by the files within a folder.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FolderUsage {
+    /// The folder's path, ending with a `/`.
+    pub path: String,
+    pub files: usize,
+    /// The total length of the folder's files.
+    pub bytes: u64,
+    /// The total length of the folder's files, counting files with identical
+    /// contents once.
+    pub unique_bytes: u64,
+}
+
+/// A file being summarized by [`summarize`].
+pub struct FileUsage {
+    pub path: String,
+    pub len: u64,
+    /// The blake3 hash of the file's contents, if known. Files without a hash
+    /// are never considered duplicates.
+    pub blake3: Option<[u8; 32]>,
+}
+
+/// Totals the usage of `files` for `root` and each folder within it, up to
+/// `depth` levels below `root`. Each file counts towards every folder that
+/// contains it. The result is sorted by path.
+pub fn summarize(root: &str, depth: usize, files: &[FileUsage]) -> Vec<FolderUsage> {
+    let mut folders = BTreeMap::<&str, (FolderUsage, HashSet<[u8; 32]>)>::new();
+    for file in files {
+        let Some(relative) = file.path.strip_prefix(root) else {
+            continue;
+        };
+
+        // The root, followed by each parent folder, up to `depth`.
+        let parents = relative
+            .match_indices('/')
+            .take(depth)
+            .map(|(index, _)| &file.path[..=root.len() + index]);
+        for folder in std::iter::once(root).chain(parents) {
+            let (usage, hashes) = folders.entry(folder).or_insert_with(|| {
+                (
+                    FolderUsage {
+                        path: folder.to_string(),
+                        files: 0,
+                        bytes: 0,
+                        unique_bytes: 0,
+                    },
+                    HashSet::new(),
+                )
+            });
+            usage.files += 1;
+            usage.bytes += file.len;
+            let unique = match file.blake3 {
+                Some(hash) => hashes.insert(hash),
+                None => true,
+            };
+            if unique {
+                usage.unique_bytes += file.len;
+            }
+        }
+    }
+
+    folders.into_values().map(|(usage, _)| usage).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(path: &str, len: u64, hash: Option<u8>) -> FileUsage {
+        FileUsage {
+            path: path.to_string(),
+            len,
+            blake3: hash.map(|hash| [hash; 32]),
+        }
+    }
+
+    fn usage(path: &str, files: usize, bytes: u64, unique_bytes: u64) -> FolderUsage {
+        FolderUsage {
+            path: path.to_string(),
+            files,
+            bytes,
+            unique_bytes,
+        }
+    }
+
+    #[test]
+    fn depth() {
+        let files = [
+            file("/docs/index.html", 10, Some(1)),
+            file("/docs/v1/index.html", 20, Some(2)),
+            file("/docs/v1/api/a.html", 30, Some(3)),
+        ];
+        assert_eq!(summarize("/docs/", 0, &files), [usage("/docs/", 3, 60, 60)]);
+        assert_eq!(
+            summarize("/docs/", 1, &files),
+            [usage("/docs/", 3, 60, 60), usage("/docs/v1/", 2, 50, 50)]
+        );
+        assert_eq!(
+            summarize("/docs/", 5, &files),
+            [
+                usage("/docs/", 3, 60, 60),
+                usage("/docs/v1/", 2, 50, 50),
+                usage("/docs/v1/api/", 1, 30, 30)
+            ]
+        );
+    }
+
+    #[test]
+    fn duplicates() {
+        let files = [
+            file("/docs/v1/style.css", 10, Some(1)),
+            file("/docs/v2/style.css", 10, Some(1)),
+            file("/docs/v2/broken.css", 5, None),
+            file("/docs/v2/broken2.css", 5, None),
+        ];
+        assert_eq!(
+            summarize("/docs/", 1, &files),
+            [
+                usage("/docs/", 4, 30, 20),
+                usage("/docs/v1/", 1, 10, 10),
+                usage("/docs/v2/", 3, 20, 20)
+            ]
+        );
+    }
+}
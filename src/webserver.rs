@@ -1,12 +1,13 @@
-use std::{collections::HashSet, convert::Infallible, net::SocketAddr, str::Chars, time::Instant};
+use std::{convert::Infallible, net::SocketAddr, time::Instant};
 
 use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use bonsaidb::{
+    core::schema::SerializedCollection,
     files::FileConfig,
     server::{CustomServer, ServerDatabase},
 };
 use http::{
-    header::{CONTENT_LENGTH, IF_NONE_MATCH, LOCATION},
+    header::{CACHE_CONTROL, CONTENT_LENGTH, IF_MATCH, IF_NONE_MATCH, LOCATION},
     HeaderValue,
 };
 use hyper::{
@@ -18,18 +19,32 @@ use hyper::{
 use mime_guess::MimeGuess;
 
 use crate::{
-    schema::{DossierFiles, Metadata},
+    access_log::{AccessLog, LoggedBody},
+    api::cached_aliases,
+    metrics::metrics,
+    schema::{DossierFiles, Metadata, Project},
     CliBackend,
 };
 
-pub(crate) fn launch(server: CustomServer<CliBackend>, dossier: ServerDatabase<CliBackend>) {
+pub(crate) fn launch(
+    server: CustomServer<CliBackend>,
+    dossier: ServerDatabase<CliBackend>,
+) -> anyhow::Result<()> {
+    let access_log = AccessLog::from_env()?;
     let make_service = make_service_fn(move |conn: &AddrStream| {
         let server = server.clone();
         let dossier = dossier.clone();
+        let access_log = access_log.clone();
         let peer_addr = conn.remote_addr();
         async move {
             Ok::<_, Infallible>(service_fn(move |req| {
-                get_page_with_error_handling(req, server.clone(), dossier.clone(), peer_addr)
+                get_page_with_error_handling(
+                    req,
+                    server.clone(),
+                    dossier.clone(),
+                    peer_addr,
+                    access_log.clone(),
+                )
             }))
         }
     });
@@ -37,50 +52,210 @@ pub(crate) fn launch(server: CustomServer<CliBackend>, dossier: ServerDatabase<C
     let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
     let hyper = hyper::Server::bind(&addr).serve(make_service);
     tokio::task::spawn(async move { hyper.await });
+    Ok(())
 }
 
-async fn get_page(
+/// An endpoint served by the webserver.
+///
+/// Paths beginning with `/_` are reserved for the server itself. Every other
+/// path is looked up in [`DossierFiles`]. New endpoints are added by adding a
+/// variant, mapping its path in [`Endpoint::route`], listing its methods in
+/// [`Endpoint::allowed_methods`], and dispatching it in [`handle_request`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Endpoint {
+    WebSocket,
+    Metrics,
+    /// Responds whenever the server is running.
+    Health,
+    /// Responds successfully when the `dossier` database can be read.
+    Ready,
+    Page,
+}
+
+impl Endpoint {
+    /// Returns the endpoint that serves `path`, or `None` if `path` is an
+    /// unknown reserved route.
+    fn route(path: &str) -> Option<Self> {
+        match path {
+            "/_ws" => Some(Self::WebSocket),
+            "/_metrics" => Some(Self::Metrics),
+            "/_health" => Some(Self::Health),
+            "/_ready" => Some(Self::Ready),
+            reserved if reserved.starts_with("/_") => None,
+            _ => Some(Self::Page),
+        }
+    }
+
+    /// Returns the methods this endpoint supports. `OPTIONS` is supported by
+    /// every endpoint and is not included.
+    fn allowed_methods(self) -> &'static [Method] {
+        match self {
+            Self::WebSocket | Self::Metrics => &[Method::GET],
+            Self::Health | Self::Ready | Self::Page => &[Method::GET, Method::HEAD],
+        }
+    }
+}
+
+fn allow_header(methods: &[Method]) -> String {
+    let mut allow = String::from("OPTIONS");
+    for method in methods {
+        allow.push_str(", ");
+        allow.push_str(method.as_str());
+    }
+    allow
+}
+
+async fn handle_request(
     request: Request<Body>,
     server: CustomServer<CliBackend>,
     pages: ServerDatabase<CliBackend>,
     peer_addr: SocketAddr,
 ) -> anyhow::Result<Response<Body>> {
-    if request.uri().path() == "/_ws" {
-        return Ok(server.upgrade_websocket(peer_addr, request).await);
+    let start = Instant::now();
+
+    let endpoint = match Endpoint::route(request.uri().path()) {
+        Some(endpoint) => endpoint,
+        None => return Ok(not_found(start)),
+    };
+
+    let allowed_methods = endpoint.allowed_methods();
+    if request.method() == Method::OPTIONS {
+        return Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(ALLOW, allow_header(allowed_methods))
+            .body(Body::empty())
+            .unwrap());
+    } else if !allowed_methods.contains(request.method()) {
+        return Ok(method_not_allowed(endpoint, start));
     }
 
-    let start = Instant::now();
+    match endpoint {
+        Endpoint::WebSocket => Ok(server.upgrade_websocket(peer_addr, request).await),
+        Endpoint::Metrics => Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, prometheus::TEXT_FORMAT)
+            .body(Body::from(metrics().render()?))?),
+        Endpoint::Health => Ok(plain_text(StatusCode::OK, "ok")),
+        // Reading a single project checks that the database can be queried
+        // without loading every project.
+        Endpoint::Ready => match Project::all_async(&pages).limit(1).await {
+            Ok(_) => Ok(plain_text(StatusCode::OK, "ready")),
+            Err(err) => {
+                eprintln!("Readiness check failed: {err}");
+                Ok(plain_text(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "database unavailable",
+                ))
+            }
+        },
+        Endpoint::Page => get_page(request, pages, start).await,
+    }
+}
 
-    let path = decode_escaped_path_components(request.uri().path())?;
+/// Returns an uncacheable plain text response, for endpoints that are polled
+/// by load balancers and monitoring.
+fn plain_text(status: StatusCode, body: impl Into<Body>) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+        .header(CACHE_CONTROL, "no-store")
+        .body(body.into())
+        .unwrap()
+}
+
+fn method_not_allowed(endpoint: Endpoint, start: Instant) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::METHOD_NOT_ALLOWED)
+        .header(ALLOW, allow_header(endpoint.allowed_methods()))
+        .header("Server-Timing", server_timings_header(start))
+        .body(Body::empty())
+        .unwrap()
+}
+
+fn not_found(start: Instant) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .header("Server-Timing", server_timings_header(start))
+        .body(Body::from("Not found"))
+        .unwrap()
+}
+
+async fn get_page(
+    request: Request<Body>,
+    pages: ServerDatabase<CliBackend>,
+    start: Instant,
+) -> anyhow::Result<Response<Body>> {
+    let path = match normalize_path(request.uri().path()) {
+        Ok(NormalizedPath::Canonical(path)) => path,
+        Ok(NormalizedPath::Redirect(mut location)) => {
+            if let Some(query) = request.uri().query() {
+                location.push('?');
+                location.push_str(query);
+            }
+            return Ok(Response::builder()
+                .header(LOCATION, location)
+                .status(StatusCode::PERMANENT_REDIRECT)
+                .header("Server-Timing", server_timings_header(start))
+                .body(Body::empty())?);
+        }
+        Err(err) => {
+            return Ok(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .header("Server-Timing", server_timings_header(start))
+                .body(Body::from(err.to_string()))
+                .unwrap())
+        }
+    };
 
+    let lookup_timer = metrics().file_lookup_duration.start_timer();
+    let path = match resolve_alias(&path, request.uri().path(), &pages).await? {
+        Some(AliasResolution::Rewrite(path)) => path,
+        Some(AliasResolution::Redirect(mut location)) => {
+            lookup_timer.observe_duration();
+            if let Some(query) = request.uri().query() {
+                location.push('?');
+                location.push_str(query);
+            }
+            // Aliases can be repointed, so the redirect must not be cached
+            // permanently.
+            return Ok(Response::builder()
+                .header(LOCATION, location)
+                .status(StatusCode::TEMPORARY_REDIRECT)
+                .header("Server-Timing", server_timings_header(start))
+                .body(Body::empty())?);
+        }
+        None => path,
+    };
     let mut file = DossierFiles::load_async(&path, &pages).await?;
+    let file_is_exact_match = file.is_some();
 
     if file.is_none() {
         file = DossierFiles::list_async(&path, &pages)
             .await?
             .into_iter()
             .find(|file| file.name().starts_with("index."));
-        if file.is_some() && !path.ends_with('/') {
-            let (_, folder_name) = path.rsplit_once('/').unwrap_or(("", &path));
-            // Redirect to the folder's root.
-            return Ok(Response::builder()
-                .header(LOCATION, format!("./{folder_name}/"))
-                .status(StatusCode::TEMPORARY_REDIRECT)
-                .body(Body::empty())?);
-        }
     }
+    lookup_timer.observe_duration();
 
     let mut file = match file {
         Some(file) => file,
-        None => {
-            return Ok(Response::builder()
-                .status(StatusCode::NOT_FOUND)
-                .header("Server-Timing", server_timings_header(start))
-                .body(Body::from("Not found"))
-                .unwrap())
-        }
+        None => return Ok(not_found(start)),
     };
 
+    if !file_is_exact_match && !path.ends_with('/') {
+        // Use the still-encoded name so that the Location header is valid.
+        let (_, folder_name) = request
+            .uri()
+            .path()
+            .rsplit_once('/')
+            .unwrap_or(("", request.uri().path()));
+        // Redirect to the folder's root.
+        return Ok(Response::builder()
+            .header(LOCATION, format!("./{folder_name}/"))
+            .status(StatusCode::TEMPORARY_REDIRECT)
+            .body(Body::empty())?);
+    }
+
     match request.method() {
         &Method::GET => {
             let (send_body, response) = construct_page_response(
@@ -109,13 +284,80 @@ async fn get_page(
                 .body(Body::empty())
                 .unwrap())
         }
-        &Method::OPTIONS => Ok(Response::builder()
-            .status(StatusCode::OK)
-            .header(ALLOW, "OPTIONS, GET, HEAD")
-            .body(Body::empty())
-            .unwrap()),
-        method => anyhow::bail!("unsupported method: {method:?}"),
+        _ => Ok(method_not_allowed(Endpoint::Page, start)),
+    }
+}
+
+enum AliasResolution {
+    /// The file should be served from this path instead.
+    Rewrite(String),
+    /// The client should be redirected to this encoded location.
+    Redirect(String),
+}
+
+/// Finds the alias that contains `path`, if any. `raw_path` is the
+/// still-encoded form of `path`.
+async fn resolve_alias(
+    path: &str,
+    raw_path: &str,
+    pages: &ServerDatabase<CliBackend>,
+) -> anyhow::Result<Option<AliasResolution>> {
+    let aliases = cached_aliases(pages).await?;
+    if aliases.is_empty() {
+        return Ok(None);
+    }
+
+    if !path.ends_with('/') && aliases.contains_key(&format!("{path}/")) {
+        // Relative links only work within the alias's folder.
+        return Ok(Some(AliasResolution::Redirect(format!("{raw_path}/"))));
+    }
+
+    for candidate in alias_candidates(path) {
+        if let Some(alias) = aliases.get(candidate) {
+            let resolution = if alias.redirect {
+                let raw_remaining = raw_path_after(raw_path, candidate.matches('/').count());
+                AliasResolution::Redirect(format!("{}{raw_remaining}", encode_path(&alias.target)))
+            } else {
+                AliasResolution::Rewrite(format!("{}{}", alias.target, &path[candidate.len()..]))
+            };
+            return Ok(Some(resolution));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Returns the folders containing `path` that could be aliases, from the
+/// deepest to the shallowest. Aliases must be within a project, so `/` and
+/// `/project/` are never returned.
+fn alias_candidates(path: &str) -> impl Iterator<Item = &str> {
+    path.match_indices('/')
+        .skip(2)
+        .map(|(index, _)| &path[..=index])
+        .collect::<Vec<_>>()
+        .into_iter()
+        .rev()
+}
+
+/// Returns the portion of `raw_path` after its first `segments` slashes.
+fn raw_path_after(raw_path: &str, segments: usize) -> &str {
+    segments
+        .checked_sub(1)
+        .and_then(|skip| raw_path.match_indices('/').nth(skip))
+        .map_or("", |(index, _)| &raw_path[index + 1..])
+}
+
+/// Percent-encodes the bytes of `path` that aren't allowed in a URI path.
+fn encode_path(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~!$&'()*+,;=:@/".contains(&byte) {
+            encoded.push(char::from(byte));
+        } else {
+            encoded.push_str(&format!("%{byte:02X}"));
+        }
     }
+    encoded
 }
 
 fn server_timings_header(start: Instant) -> String {
@@ -128,40 +370,207 @@ fn construct_page_response(
     metadata: Option<&Metadata>,
     start: Instant,
 ) -> (bool, http::response::Builder) {
-    let (send_body, mut response) = match (request.headers().get(IF_NONE_MATCH), metadata) {
-        (Some(etags), Some(metadata))
-            if parse_etags(etags)
-                .unwrap_or_default()
-                .contains(&metadata.blake3) =>
-        {
-            (false, Response::builder().status(StatusCode::NOT_MODIFIED))
-        }
-        _ => (true, Response::builder().status(StatusCode::OK)),
+    let (send_body, mut response) = match evaluate_preconditions(request, metadata) {
+        Precondition::Proceed => (true, Response::builder().status(StatusCode::OK)),
+        Precondition::NotModified => (false, Response::builder().status(StatusCode::NOT_MODIFIED)),
+        Precondition::Failed => (
+            false,
+            Response::builder().status(StatusCode::PRECONDITION_FAILED),
+        ),
     };
     if let Some(mime_type) = mime_guess.first_raw() {
         response = response.header(CONTENT_TYPE, mime_type);
     }
     if let Some(metadata) = metadata {
-        response = response.header(ETAG, URL_SAFE_NO_PAD.encode(metadata.blake3));
+        response = response.header(ETAG, entity_tag(metadata));
     }
     response = response.header("Server-Timing", server_timings_header(start));
     (send_body, response)
 }
 
-fn parse_etags(etags: &HeaderValue) -> Option<HashSet<[u8; 32]>> {
-    let etags = etags.to_str().ok()?;
-    let fields = etags.split(',');
-    let mut parsed_tags = HashSet::new();
-    for quoted_tag in fields {
-        let tag = quoted_tag.split('"').nth(1)?;
-        if let Ok(tag) = URL_SAFE_NO_PAD.decode(tag) {
-            if let Ok(tag) = tag.try_into() {
-                parsed_tags.insert(tag);
+/// Returns the strong entity tag for a file with `metadata`, including the
+/// surrounding quotes.
+fn entity_tag(metadata: &Metadata) -> String {
+    format!("\"{}\"", URL_SAFE_NO_PAD.encode(metadata.blake3))
+}
+
+#[derive(Debug, Eq, PartialEq)]
+enum Precondition {
+    Proceed,
+    NotModified,
+    Failed,
+}
+
+/// Evaluates the `If-Match` and `If-None-Match` headers in the order
+/// described by RFC 9110 section 13.2.2.
+fn evaluate_preconditions(request: &Request<Body>, metadata: Option<&Metadata>) -> Precondition {
+    let current = metadata.map(|metadata| metadata.blake3);
+    if let Some(if_match) = request.headers().get(IF_MATCH) {
+        // If-Match uses the strong comparison function. Files without metadata
+        // have no entity tag, and can only match a wildcard.
+        if !parse_etags(if_match).matches(current.as_ref(), true) {
+            return Precondition::Failed;
+        }
+    }
+
+    if let Some(if_none_match) = request.headers().get(IF_NONE_MATCH) {
+        if parse_etags(if_none_match).matches(current.as_ref(), false) {
+            return match request.method() {
+                &Method::GET | &Method::HEAD => Precondition::NotModified,
+                _ => Precondition::Failed,
+            };
+        }
+    }
+
+    Precondition::Proceed
+}
+
+/// The parsed value of an `If-Match` or `If-None-Match` header.
+#[derive(Debug, Eq, PartialEq)]
+enum EntityTags {
+    /// The `*` wildcard, which matches any current representation.
+    Any,
+    /// A list of entity tags. Entries that were malformed are omitted.
+    List(Vec<EntityTag>),
+}
+
+impl EntityTags {
+    /// Returns true if `current` matches any of the tags. When `strong` is
+    /// true, weak tags never match.
+    ///
+    /// `current` is `None` when the file exists but has no computed hash yet.
+    fn matches(&self, current: Option<&[u8; 32]>, strong: bool) -> bool {
+        match self {
+            EntityTags::Any => true,
+            EntityTags::List(tags) => current.is_some_and(|current| {
+                tags.iter()
+                    .any(|tag| (!strong || !tag.weak) && tag.blake3() == Some(*current))
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+struct EntityTag {
+    weak: bool,
+    opaque: String,
+}
+
+impl EntityTag {
+    /// Decodes the opaque tag as a hash previously produced by [`entity_tag`].
+    /// Tags produced by other servers return `None`.
+    fn blake3(&self) -> Option<[u8; 32]> {
+        URL_SAFE_NO_PAD
+            .decode(&self.opaque)
+            .ok()
+            .and_then(|tag| tag.try_into().ok())
+    }
+}
+
+/// Parses an `If-Match`/`If-None-Match` header value following RFC 9110
+/// section 8.8.3:
+///
+/// ```text
+/// If-None-Match = "*" / #entity-tag
+/// entity-tag    = [ weak ] opaque-tag
+/// weak          = %s"W/"
+/// opaque-tag    = DQUOTE *etagc DQUOTE
+/// etagc         = %x21 / %x23-7E / obs-text
+/// ```
+///
+/// A malformed list member is skipped rather than invalidating the entire
+/// header, so that one bad tag can't prevent the others from matching.
+fn parse_etags(header: &HeaderValue) -> EntityTags {
+    let header = header.as_bytes();
+    if trim_ows(header) == b"*" {
+        return EntityTags::Any;
+    }
+
+    let mut tags = Vec::new();
+    let mut remaining = header;
+    loop {
+        remaining = skip_list_separators(remaining);
+        if remaining.is_empty() {
+            break;
+        }
+
+        if let Some((tag, rest)) = parse_entity_tag(remaining) {
+            let rest = trim_leading_ows(rest);
+            if rest.is_empty() || rest[0] == b',' {
+                tags.push(tag);
+                remaining = rest;
+                continue;
             }
+            // Trailing garbage after the closing quote invalidates this
+            // member.
+            remaining = rest;
+        }
+
+        // Skip to the next list member.
+        match remaining.iter().position(|&ch| ch == b',') {
+            Some(index) => remaining = &remaining[index..],
+            None => break,
         }
     }
 
-    Some(parsed_tags)
+    EntityTags::List(tags)
+}
+
+/// Parses a single `entity-tag` from the start of `value`, returning the tag
+/// and the unparsed remainder.
+fn parse_entity_tag(value: &[u8]) -> Option<(EntityTag, &[u8])> {
+    let (weak, value) = match value.strip_prefix(b"W/") {
+        Some(value) => (true, value),
+        None => (false, value),
+    };
+    let value = value.strip_prefix(b"\"")?;
+    let end = value.iter().position(|&ch| ch == b'"')?;
+    let opaque = &value[..end];
+    if !opaque
+        .iter()
+        .all(|&ch| ch == 0x21 || (0x23..=0x7E).contains(&ch) || ch >= 0x80)
+    {
+        return None;
+    }
+
+    Some((
+        EntityTag {
+            weak,
+            opaque: String::from_utf8_lossy(opaque).into_owned(),
+        },
+        &value[end + 1..],
+    ))
+}
+
+fn is_ows(ch: u8) -> bool {
+    ch == b' ' || ch == b'\t'
+}
+
+fn trim_leading_ows(value: &[u8]) -> &[u8] {
+    let start = value
+        .iter()
+        .position(|&ch| !is_ows(ch))
+        .unwrap_or(value.len());
+    &value[start..]
+}
+
+fn trim_ows(value: &[u8]) -> &[u8] {
+    let value = trim_leading_ows(value);
+    let end = value
+        .iter()
+        .rposition(|&ch| !is_ows(ch))
+        .map_or(0, |end| end + 1);
+    &value[..end]
+}
+
+/// Skips whitespace and empty list elements, which RFC 9110 section 5.6.1
+/// requires recipients to accept.
+fn skip_list_separators(value: &[u8]) -> &[u8] {
+    let start = value
+        .iter()
+        .position(|&ch| !is_ows(ch) && ch != b',')
+        .unwrap_or(value.len());
+    &value[start..]
 }
 
 async fn get_page_with_error_handling(
@@ -169,51 +578,429 @@ async fn get_page_with_error_handling(
     server: CustomServer<CliBackend>,
     pages: ServerDatabase<CliBackend>,
     peer_addr: SocketAddr,
-) -> Result<Response<Body>, Infallible> {
-    Ok(get_page(request, server, pages, peer_addr)
+    access_log: AccessLog,
+) -> Result<Response<LoggedBody>, Infallible> {
+    let start = Instant::now();
+    let request_info = access_log.request_info(&request, peer_addr);
+    let project = metrics().project_label(request.uri().path());
+    let response = handle_request(request, server, pages, peer_addr)
         .await
         .unwrap_or_else(|err| {
             Response::builder()
                 .status(StatusCode::INTERNAL_SERVER_ERROR)
                 .body(Body::from(format!("an error occurred: {err}").into_bytes()))
                 .unwrap()
-        }))
+        });
+
+    let status = response.status();
+    let labels = [project.as_str(), status.as_str()];
+    metrics().http_requests.with_label_values(&labels).inc();
+    metrics()
+        .http_request_duration
+        .with_label_values(&labels)
+        .observe(start.elapsed().as_secs_f64());
+    let bytes_served = metrics().http_response_bytes.with_label_values(&[&project]);
+    Ok(access_log.log_response(request_info, response, bytes_served))
 }
 
-fn decode_escaped_path_components(path: &str) -> anyhow::Result<String> {
-    PercentDecoder {
-        chars: path.chars(),
-    }
-    .collect()
+#[derive(thiserror::Error, Debug, Eq, PartialEq)]
+enum PathError {
+    #[error("paths must start with '/'")]
+    NotAbsolute,
+    #[error("invalid percent escape sequence")]
+    InvalidEscape,
+    #[error("paths must be valid UTF-8")]
+    InvalidUtf8,
+    #[error("/ is invalid in a path segment")]
+    EscapedSlash,
+    #[error("control characters are invalid in a path segment")]
+    ControlCharacter,
 }
 
-struct PercentDecoder<'a> {
-    chars: Chars<'a>,
+#[derive(Debug, Eq, PartialEq)]
+enum NormalizedPath {
+    /// The path was already canonical. Contains the decoded path.
+    Canonical(String),
+    /// The path contained dot segments or empty segments. Contains the
+    /// canonical path, re-encoded with [`encode_path`], that the client should
+    /// be redirected to. Segments aren't copied from the request as-is, since
+    /// a raw `\` would let `/./\example.com` redirect to another host.
+    Redirect(String),
 }
 
-impl<'a> Iterator for PercentDecoder<'a> {
-    type Item = anyhow::Result<char>;
+/// Decodes and normalizes an absolute request path as described by RFC 3986.
+///
+/// Each segment is percent-decoded as UTF-8. `.` and `..` segments are
+/// resolved, and empty segments (duplicate slashes) are removed. `..` never
+/// escapes the root. If any normalization was required, the canonical path is
+/// returned as a redirect rather than being served directly.
+///
+/// Unlike form encoding, `+` is not treated as a space.
+fn normalize_path(path: &str) -> Result<NormalizedPath, PathError> {
+    let path = path.strip_prefix('/').ok_or(PathError::NotAbsolute)?;
+
+    let mut canonical = true;
+    let mut trailing_slash = false;
+    let mut segments = Vec::<String>::new();
+    let mut raw_segments = path.split('/').peekable();
+    while let Some(raw) = raw_segments.next() {
+        let is_last = raw_segments.peek().is_none();
+        let decoded = decode_path_segment(raw)?;
+        match decoded.as_str() {
+            "" if is_last => trailing_slash = true,
+            "" => canonical = false,
+            "." => {
+                canonical = false;
+                trailing_slash = is_last;
+            }
+            ".." => {
+                canonical = false;
+                segments.pop();
+                trailing_slash = is_last;
+            }
+            _ => {
+                trailing_slash = false;
+                segments.push(decoded);
+            }
+        }
+    }
+
+    let mut joined = String::with_capacity(path.len() + 1);
+    for segment in &segments {
+        joined.push('/');
+        joined.push_str(segment);
+    }
+    if trailing_slash || joined.is_empty() {
+        joined.push('/');
+    }
+
+    if canonical {
+        Ok(NormalizedPath::Canonical(joined))
+    } else {
+        Ok(NormalizedPath::Redirect(encode_path(&joined)))
+    }
+}
 
-    fn next(&mut self) -> Option<Self::Item> {
-        match self.chars.next()? {
-            '%' => {
-                let mut hex = [0; 2];
-                hex[0] = match self.chars.next().map(u8::try_from) {
-                    Some(Ok(ch)) => ch,
-                    _ => return Some(Err(anyhow::anyhow!("invalid percent escape sequence"))),
-                };
-                hex[1] = match self.chars.next().map(u8::try_from) {
-                    Some(Ok(ch)) => ch,
-                    _ => return Some(Err(anyhow::anyhow!("invalid percent escape sequence"))),
-                };
-                match u8::from_str_radix(std::str::from_utf8(&hex).unwrap(), 16) {
-                    Ok(b'/') => Some(Err(anyhow::anyhow!("/ is invalid in a path segment"))),
-                    Ok(byte) => Some(Ok(char::from(byte))),
-                    Err(err) => Some(Err(anyhow::anyhow!(err))),
-                }
+fn decode_path_segment(segment: &str) -> Result<String, PathError> {
+    let mut decoded = Vec::with_capacity(segment.len());
+    let mut bytes = segment.bytes();
+    while let Some(byte) = bytes.next() {
+        if byte == b'%' {
+            let high = bytes.next().and_then(hex_value);
+            let low = bytes.next().and_then(hex_value);
+            match (high, low) {
+                (Some(high), Some(low)) => match high << 4 | low {
+                    b'/' => return Err(PathError::EscapedSlash),
+                    byte => decoded.push(byte),
+                },
+                _ => return Err(PathError::InvalidEscape),
             }
-            '+' => Some(Ok(' ')),
-            other => Some(Ok(other)),
+        } else {
+            decoded.push(byte);
         }
     }
+
+    let decoded = String::from_utf8(decoded).map_err(|_| PathError::InvalidUtf8)?;
+    if decoded.chars().any(char::is_control) {
+        return Err(PathError::ControlCharacter);
+    }
+
+    Ok(decoded)
+}
+
+fn hex_value(ch: u8) -> Option<u8> {
+    match ch {
+        b'0'..=b'9' => Some(ch - b'0'),
+        b'a'..=b'f' => Some(ch - b'a' + 10),
+        b'A'..=b'F' => Some(ch - b'A' + 10),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag(weak: bool, opaque: &str) -> EntityTag {
+        EntityTag {
+            weak,
+            opaque: opaque.to_string(),
+        }
+    }
+
+    fn parse(value: &str) -> EntityTags {
+        parse_etags(&HeaderValue::from_str(value).unwrap())
+    }
+
+    #[test]
+    fn entity_tag_is_quoted() {
+        let metadata = Metadata {
+            blake3: [7; 32],
+            written_at: 0,
+        };
+        let etag = entity_tag(&metadata);
+        assert!(etag.starts_with('"') && etag.ends_with('"'));
+        let EntityTags::List(tags) = parse(&etag) else {
+            unreachable!("not a list")
+        };
+        assert_eq!(tags.len(), 1);
+        assert!(!tags[0].weak);
+        assert_eq!(tags[0].blake3(), Some(metadata.blake3));
+    }
+
+    #[test]
+    fn parse_wildcard() {
+        assert_eq!(parse("*"), EntityTags::Any);
+        assert_eq!(parse("  *\t"), EntityTags::Any);
+        // A wildcard inside of a list isn't valid.
+        assert_eq!(parse("*, \"a\""), EntityTags::List(vec![tag(false, "a")]));
+    }
+
+    #[test]
+    fn parse_lists() {
+        assert_eq!(parse("\"a\""), EntityTags::List(vec![tag(false, "a")]));
+        assert_eq!(parse("W/\"a\""), EntityTags::List(vec![tag(true, "a")]));
+        assert_eq!(
+            parse("\"a\", W/\"b\",\"c\""),
+            EntityTags::List(vec![tag(false, "a"), tag(true, "b"), tag(false, "c")])
+        );
+        assert_eq!(parse("\"\""), EntityTags::List(vec![tag(false, "")]));
+        // Commas are valid etagc characters.
+        assert_eq!(parse("\"a,b\""), EntityTags::List(vec![tag(false, "a,b")]));
+        // Empty list elements are ignored.
+        assert_eq!(
+            parse(", ,\"a\",,  ,\"b\","),
+            EntityTags::List(vec![tag(false, "a"), tag(false, "b")])
+        );
+        assert_eq!(parse(""), EntityTags::List(Vec::new()));
+    }
+
+    #[test]
+    fn parse_skips_malformed_members() {
+        assert_eq!(parse("a, \"b\""), EntityTags::List(vec![tag(false, "b")]));
+        assert_eq!(
+            parse("\"a\"junk, \"b\""),
+            EntityTags::List(vec![tag(false, "b")])
+        );
+        assert_eq!(
+            parse("w/\"a\", \"b\""),
+            EntityTags::List(vec![tag(false, "b")])
+        );
+        assert_eq!(parse("\"a\", \"b"), EntityTags::List(vec![tag(false, "a")]));
+        assert_eq!(
+            parse("\"a b\", \"c\""),
+            EntityTags::List(vec![tag(false, "c")])
+        );
+    }
+
+    #[test]
+    fn matching() {
+        let hash = [1; 32];
+        let strong = format!("\"{}\"", URL_SAFE_NO_PAD.encode(hash));
+        let weak = format!("W/{strong}");
+        let other = format!("\"{}\"", URL_SAFE_NO_PAD.encode([2; 32]));
+
+        assert!(parse(&strong).matches(Some(&hash), true));
+        assert!(parse(&strong).matches(Some(&hash), false));
+        assert!(!parse(&weak).matches(Some(&hash), true));
+        assert!(parse(&weak).matches(Some(&hash), false));
+        assert!(!parse(&other).matches(Some(&hash), false));
+        assert!(parse(&format!("{other}, {strong}")).matches(Some(&hash), true));
+        assert!(!parse(&strong).matches(None, false));
+
+        assert!(parse("*").matches(Some(&hash), true));
+        assert!(parse("*").matches(None, true));
+        assert!(!parse("\"not-a-hash\"").matches(Some(&hash), false));
+    }
+
+    #[test]
+    fn routing() {
+        assert_eq!(Endpoint::route("/_ws"), Some(Endpoint::WebSocket));
+        assert_eq!(Endpoint::route("/_metrics"), Some(Endpoint::Metrics));
+        assert_eq!(Endpoint::route("/_health"), Some(Endpoint::Health));
+        assert_eq!(Endpoint::route("/_ready"), Some(Endpoint::Ready));
+        assert_eq!(Endpoint::route("/"), Some(Endpoint::Page));
+        assert_eq!(Endpoint::route("/dossier/main/"), Some(Endpoint::Page));
+        assert_eq!(Endpoint::route("/a/_b"), Some(Endpoint::Page));
+        assert_eq!(Endpoint::route("/_"), None);
+        assert_eq!(Endpoint::route("/_ws/"), None);
+        assert_eq!(Endpoint::route("/_unknown"), None);
+    }
+
+    #[test]
+    fn allowed_methods() {
+        assert_eq!(
+            allow_header(Endpoint::Page.allowed_methods()),
+            "OPTIONS, GET, HEAD"
+        );
+        assert_eq!(
+            allow_header(Endpoint::WebSocket.allowed_methods()),
+            "OPTIONS, GET"
+        );
+        assert!(!Endpoint::Page.allowed_methods().contains(&Method::POST));
+    }
+
+    #[test]
+    fn preconditions() {
+        let metadata = Metadata {
+            blake3: [3; 32],
+            written_at: 0,
+        };
+        let etag = entity_tag(&metadata);
+        let request = |method: Method, header, value: &str| {
+            Request::builder()
+                .method(method)
+                .header(header, value)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        assert_eq!(
+            evaluate_preconditions(&request(Method::GET, IF_NONE_MATCH, &etag), Some(&metadata)),
+            Precondition::NotModified
+        );
+        assert_eq!(
+            evaluate_preconditions(&request(Method::HEAD, IF_NONE_MATCH, "*"), Some(&metadata)),
+            Precondition::NotModified
+        );
+        assert_eq!(
+            evaluate_preconditions(
+                &request(Method::GET, IF_NONE_MATCH, "\"other\""),
+                Some(&metadata)
+            ),
+            Precondition::Proceed
+        );
+        assert_eq!(
+            evaluate_preconditions(&request(Method::GET, IF_MATCH, &etag), Some(&metadata)),
+            Precondition::Proceed
+        );
+        assert_eq!(
+            evaluate_preconditions(
+                &request(Method::GET, IF_MATCH, &format!("W/{etag}")),
+                Some(&metadata)
+            ),
+            Precondition::Failed
+        );
+        assert_eq!(
+            evaluate_preconditions(&request(Method::GET, IF_MATCH, &etag), None),
+            Precondition::Failed
+        );
+        assert_eq!(
+            evaluate_preconditions(&request(Method::GET, IF_MATCH, "*"), None),
+            Precondition::Proceed
+        );
+    }
+
+    fn canonical(path: &str) -> Result<NormalizedPath, PathError> {
+        Ok(NormalizedPath::Canonical(path.to_string()))
+    }
+
+    fn redirect(path: &str) -> Result<NormalizedPath, PathError> {
+        Ok(NormalizedPath::Redirect(path.to_string()))
+    }
+
+    #[test]
+    fn canonical_paths() {
+        assert_eq!(normalize_path("/"), canonical("/"));
+        assert_eq!(normalize_path("/a"), canonical("/a"));
+        assert_eq!(normalize_path("/a/"), canonical("/a/"));
+        assert_eq!(normalize_path("/a/b.html"), canonical("/a/b.html"));
+        assert_eq!(normalize_path("/a/.hidden"), canonical("/a/.hidden"));
+        assert_eq!(normalize_path("/a/..b/c.."), canonical("/a/..b/c.."));
+    }
+
+    #[test]
+    fn percent_decoding() {
+        assert_eq!(normalize_path("/a%20b"), canonical("/a b"));
+        assert_eq!(normalize_path("/a+b"), canonical("/a+b"));
+        assert_eq!(
+            normalize_path("/%C3%A9t%C3%A9/"),
+            canonical("/\u{e9}t\u{e9}/")
+        );
+        assert_eq!(normalize_path("/%c3%a9"), canonical("/\u{e9}"));
+        assert_eq!(
+            normalize_path("/%F0%9F%A6%80.html"),
+            canonical("/\u{1f980}.html")
+        );
+        assert_eq!(normalize_path("/100%25"), canonical("/100%"));
+        assert_eq!(normalize_path("/a%2Eb"), canonical("/a.b"));
+    }
+
+    #[test]
+    fn invalid_paths() {
+        assert_eq!(normalize_path(""), Err(PathError::NotAbsolute));
+        assert_eq!(normalize_path("a/b"), Err(PathError::NotAbsolute));
+        assert_eq!(normalize_path("/%"), Err(PathError::InvalidEscape));
+        assert_eq!(normalize_path("/%4"), Err(PathError::InvalidEscape));
+        assert_eq!(normalize_path("/%zz"), Err(PathError::InvalidEscape));
+        assert_eq!(normalize_path("/%+1"), Err(PathError::InvalidEscape));
+        assert_eq!(normalize_path("/a%2Fb"), Err(PathError::EscapedSlash));
+        assert_eq!(normalize_path("/a%2fb"), Err(PathError::EscapedSlash));
+        assert_eq!(normalize_path("/%C3"), Err(PathError::InvalidUtf8));
+        assert_eq!(normalize_path("/%E9t%E9"), Err(PathError::InvalidUtf8));
+        assert_eq!(normalize_path("/%C0%AF"), Err(PathError::InvalidUtf8));
+        assert_eq!(normalize_path("/a%00"), Err(PathError::ControlCharacter));
+        assert_eq!(normalize_path("/a%0A"), Err(PathError::ControlCharacter));
+        // Invalid segments are rejected even if they would be removed by a
+        // dot segment.
+        assert_eq!(normalize_path("/%zz/.."), Err(PathError::InvalidEscape));
+    }
+
+    #[test]
+    fn dot_segments() {
+        assert_eq!(normalize_path("/."), redirect("/"));
+        assert_eq!(normalize_path("/./"), redirect("/"));
+        assert_eq!(normalize_path("/.."), redirect("/"));
+        assert_eq!(normalize_path("/../.."), redirect("/"));
+        assert_eq!(normalize_path("/../a"), redirect("/a"));
+        assert_eq!(normalize_path("/a/./b"), redirect("/a/b"));
+        assert_eq!(normalize_path("/a/b/.."), redirect("/a/"));
+        assert_eq!(normalize_path("/a/b/../"), redirect("/a/"));
+        assert_eq!(normalize_path("/a/b/."), redirect("/a/b/"));
+        assert_eq!(normalize_path("/a/b/../../../c"), redirect("/c"));
+        assert_eq!(normalize_path("/a/%2e%2E/b"), redirect("/b"));
+        assert_eq!(normalize_path("/a/%2e/b"), redirect("/a/b"));
+        // Kept segments are re-encoded rather than copied from the request.
+        assert_eq!(normalize_path("/%C3%A9/./a%20b"), redirect("/%C3%A9/a%20b"));
+        assert_eq!(normalize_path("/%c3%a9/./a"), redirect("/%C3%A9/a"));
+        // A backslash would otherwise be resolved by browsers as `//`, making
+        // the redirect point at another host.
+        assert_eq!(normalize_path("/./\\evil.com"), redirect("/%5Cevil.com"));
+        assert_eq!(normalize_path("/..//\\evil.com"), redirect("/%5Cevil.com"));
+    }
+
+    #[test]
+    fn empty_segments() {
+        assert_eq!(normalize_path("//"), redirect("/"));
+        assert_eq!(normalize_path("//a"), redirect("/a"));
+        assert_eq!(normalize_path("/a//b"), redirect("/a/b"));
+        assert_eq!(normalize_path("/a///b//"), redirect("/a/b/"));
+        assert_eq!(normalize_path("/a/b//"), redirect("/a/b/"));
+    }
+
+    #[test]
+    fn alias_candidates_are_deepest_first() {
+        assert_eq!(
+            alias_candidates("/project/latest/docs/index.html").collect::<Vec<_>>(),
+            ["/project/latest/docs/", "/project/latest/"]
+        );
+        assert_eq!(
+            alias_candidates("/project/latest/").collect::<Vec<_>>(),
+            ["/project/latest/"]
+        );
+        assert_eq!(alias_candidates("/project/index.html").count(), 0);
+        assert_eq!(alias_candidates("/").count(), 0);
+    }
+
+    #[test]
+    fn alias_redirect_paths() {
+        assert_eq!(
+            raw_path_after("/project/latest/a%20b/c.html", 3),
+            "a%20b/c.html"
+        );
+        assert_eq!(raw_path_after("/project/latest/", 3), "");
+        assert_eq!(raw_path_after("/project/", 0), "");
+        assert_eq!(encode_path("/project/v0.5/"), "/project/v0.5/");
+        assert_eq!(encode_path("/project/a b/ü?"), "/project/a%20b/%C3%BC%3F");
+    }
 }